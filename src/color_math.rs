@@ -0,0 +1,438 @@
+//! Conversions between the color spaces used by the color commands, all colors are passed around as 0xRRGGBB
+
+use std::fmt;
+
+/// Dark theme background of the Discord client - colors are mostly seen against this
+pub const DISCORD_DARK: u32 = 0x313338;
+pub const DISCORD_LIGHT: u32 = 0xffffff;
+
+/// The named CSS colors, used to give a color a recognizable name
+const CSS_COLORS: [(&str, u32); 138] = [
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4), ("azure", 0xf0ffff), ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4), ("black", 0x000000), ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e), ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c), ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b), ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400), ("darkkhaki", 0xbdb76b), ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f), ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000), ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b), ("darkslategray", 0x2f4f4f), ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3), ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969), ("dodgerblue", 0x1e90ff), ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22), ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff), ("gold", 0xffd700), ("goldenrod", 0xdaa520),
+    ("gray", 0x808080), ("green", 0x008000), ("greenyellow", 0xadff2f),
+    ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa), ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6), ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90), ("lightpink", 0xffb6c1), ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa), ("lightslategray", 0x778899),
+    ("lightsteelblue", 0xb0c4de), ("lightyellow", 0xffffe0), ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32), ("linen", 0xfaf0e6), ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa), ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371), ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5), ("navajowhite", 0xffdead), ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6), ("olive", 0x808000), ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093), ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f), ("pink", 0xffc0cb), ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513), ("salmon", 0xfa8072), ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57), ("seashell", 0xfff5ee), ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090), ("snow", 0xfffafa), ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4), ("tan", 0xd2b48c), ("teal", 0x008080),
+    ("thistle", 0xd8bfd8), ("tomato", 0xff6347), ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee), ("wheat", 0xf5deb3), ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00), ("yellowgreen", 0x9acd32),
+];
+
+/// Hue in degrees (0 - 360), saturation and lightness from 0 to 1
+#[derive(Clone, Copy)]
+pub struct Hsl {
+    pub h: f64,
+    pub s: f64,
+    pub l: f64,
+}
+
+/// Hue in degrees (0 - 360), saturation and value from 0 to 1
+#[derive(Clone, Copy)]
+pub struct Hsv {
+    pub h: f64,
+    pub s: f64,
+    pub v: f64,
+}
+
+/// CIELAB with a D65 white point
+#[derive(Clone, Copy)]
+pub struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// A color code that is not 1 to 6 hexadecimal digits
+#[derive(Debug)]
+pub struct InvalidHex(String);
+
+impl fmt::Display for InvalidHex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} is not a color code, use 1 to 6 hex digits (eg. #ff7518)", self.0)
+    }
+}
+
+impl std::error::Error for InvalidHex {}
+
+/// Parses a hexadecimal color code, with or without a leading # or 0x
+pub fn parse_hex(code: &str) -> Result<u32, InvalidHex> {
+    let code = code.trim();
+    let digits = ["#", "0x", "0X"]
+        .iter()
+        .find_map(|prefix| code.strip_prefix(prefix))
+        .unwrap_or(code);
+
+    // from_str_radix would also take a sign
+    if digits.is_empty() || digits.len() > 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(InvalidHex(code.to_string()));
+    }
+
+    u32::from_str_radix(digits, 16).map_err(|_| InvalidHex(code.to_string()))
+}
+
+fn channels(color: u32) -> (f64, f64, f64) {
+    (
+        ((color >> 16) & 0xff) as f64 / 255.0,
+        ((color >> 8) & 0xff) as f64 / 255.0,
+        (color & 0xff) as f64 / 255.0,
+    )
+}
+
+fn from_channels(r: f64, g: f64, b: f64) -> u32 {
+    let to_byte = |c: f64| (c.clamp(0.0, 1.0) * 255.0).round() as u32;
+    (to_byte(r) << 16) | (to_byte(g) << 8) | to_byte(b)
+}
+
+/// Hue in degrees and the min / max channel, shared by HSL and HSV
+fn hue(color: u32) -> (f64, f64, f64) {
+    let (r, g, b) = channels(color);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let h = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * (((g - b) / delta).rem_euclid(6.0))
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    (h, min, max)
+}
+
+/// RGB from hue, chroma and the amount to add to every channel
+fn from_hue(h: f64, chroma: f64, m: f64) -> u32 {
+    let h = h.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (h.rem_euclid(2.0) - 1.0).abs());
+
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+
+    from_channels(r + m, g + m, b + m)
+}
+
+pub fn to_hsl(color: u32) -> Hsl {
+    let (h, min, max) = hue(color);
+    let l = (max + min) / 2.0;
+    let s = if max == min { 0.0 } else { (max - min) / (1.0 - (2.0 * l - 1.0).abs()) };
+
+    Hsl { h, s, l }
+}
+
+pub fn from_hsl(hsl: Hsl) -> u32 {
+    let chroma = (1.0 - (2.0 * hsl.l - 1.0).abs()) * hsl.s;
+    from_hue(hsl.h, chroma, hsl.l - chroma / 2.0)
+}
+
+pub fn to_hsv(color: u32) -> Hsv {
+    let (h, min, max) = hue(color);
+    let s = if max == 0.0 { 0.0 } else { (max - min) / max };
+
+    Hsv { h, s, v: max }
+}
+
+pub fn from_hsv(hsv: Hsv) -> u32 {
+    let chroma = hsv.v * hsv.s;
+    from_hue(hsv.h, chroma, hsv.v - chroma)
+}
+
+/// Cyan, magenta, yellow and key (black) from 0 to 1
+pub fn to_cmyk(color: u32) -> (f64, f64, f64, f64) {
+    let (r, g, b) = channels(color);
+    let k = 1.0 - r.max(g).max(b);
+
+    if k >= 1.0 {
+        return (0.0, 0.0, 0.0, 1.0);
+    }
+
+    ((1.0 - r - k) / (1.0 - k), (1.0 - g - k) / (1.0 - k), (1.0 - b - k) / (1.0 - k), k)
+}
+
+/// The CSS color name closest to the given color, and how far off it is (CIE76)
+pub fn nearest_css_name(color: u32) -> (&'static str, f64) {
+    CSS_COLORS
+        .iter()
+        .map(|(name, css)| (*name, delta_e(color, *css)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or(("black", 0.0))
+}
+
+/// WCAG rating for a contrast ratio, as used for normal text
+pub fn contrast_rating(ratio: f64) -> &'static str {
+    match ratio {
+        r if r >= 7.0 => "AAA",
+        r if r >= 4.5 => "AA",
+        r if r >= 3.0 => "AA Large",
+        _ => "Fail",
+    }
+}
+
+/// sRGB channel to linear light
+fn linearize(c: f64) -> f64 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+pub fn to_lab(color: u32) -> Lab {
+    let (r, g, b) = channels(color);
+    let (r, g, b) = (linearize(r), linearize(g), linearize(b));
+
+    // Linear sRGB to XYZ, scaled by the D65 white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f64| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    Lab {
+        l: 116.0 * fy - 16.0,
+        a: 500.0 * (fx - fy),
+        b: 200.0 * (fy - fz),
+    }
+}
+
+/// CIE76 color difference - anything below ~2.3 is hard to tell apart
+pub fn delta_e(a: u32, b: u32) -> f64 {
+    let (a, b) = (to_lab(a), to_lab(b));
+    ((a.l - b.l).powi(2) + (a.a - b.a).powi(2) + (a.b - b.b).powi(2)).sqrt()
+}
+
+/// WCAG relative luminance
+pub fn luminance(color: u32) -> f64 {
+    let (r, g, b) = channels(color);
+    0.2126 * linearize(r) + 0.7152 * linearize(g) + 0.0722 * linearize(b)
+}
+
+/// WCAG contrast ratio, from 1 (no contrast) to 21
+pub fn contrast_ratio(a: u32, b: u32) -> f64 {
+    let (la, lb) = (luminance(a), luminance(b));
+    (la.max(lb) + 0.05) / (la.min(lb) + 0.05)
+}
+
+/// Readable as a name color on both the dark and light Discord themes
+pub fn is_readable(color: u32) -> bool {
+    contrast_ratio(color, DISCORD_DARK) >= 3.0 && contrast_ratio(color, DISCORD_LIGHT) >= 2.0
+}
+
+/// Rotates the hue of a color by the given amount of degrees
+pub fn rotate_hue(color: u32, degrees: f64) -> u32 {
+    let hsl = to_hsl(color);
+    from_hsl(Hsl { h: hsl.h + degrees, ..hsl })
+}
+
+pub fn complement(color: u32) -> u32 {
+    rotate_hue(color, 180.0)
+}
+
+/// Small deterministic random generator (SplitMix64), so a seed always gives the same colors
+pub struct ColorRng(u64);
+
+impl ColorRng {
+    pub fn new(seed: u64) -> Self {
+        ColorRng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform float between min and max
+    fn range(&mut self, min: f64, max: f64) -> f64 {
+        let unit = (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64;
+        min + unit * (max - min)
+    }
+
+    /// A random color - vivid colors are picked through HSV so they are not all greyish
+    pub fn color(&mut self) -> u32 {
+        from_hsv(Hsv {
+            h: self.range(0.0, 360.0),
+            s: self.range(0.35, 1.0),
+            v: self.range(0.45, 1.0),
+        })
+    }
+}
+
+/// Picks `count` random colors that are clearly different from each other,
+/// optionally only ones that are readable on both Discord themes
+pub fn random_colors(rng: &mut ColorRng, count: usize, readable: bool) -> Vec<u32> {
+    let mut colors: Vec<u32> = vec![];
+
+    // Give up on the constraints eventually, so this can never loop forever
+    for _ in 0..10_000 {
+        if colors.len() == count {
+            break;
+        }
+
+        let color = rng.color();
+        let distinct = colors.iter().all(|c| delta_e(*c, color) >= 20.0);
+
+        if distinct && (!readable || is_readable(color)) {
+            colors.push(color);
+        }
+    }
+
+    colors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.01, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn hex_codes_parse_with_and_without_prefix() {
+        assert_eq!(parse_hex("#ff7518").unwrap(), 0xff7518);
+        assert_eq!(parse_hex("0x6a0dad").unwrap(), 0x6a0dad);
+        assert_eq!(parse_hex(" ABC ").unwrap(), 0xabc);
+        assert_eq!(parse_hex("#0").unwrap(), 0);
+    }
+
+    #[test]
+    fn invalid_hex_codes_are_rejected() {
+        for code in ["", "#", "0x", "#ff00ff00", "1234567", "ff0x00", "##ff0000", "+ff00", "zzzzzz", "#ff 000"] {
+            assert!(parse_hex(code).is_err(), "{:?} was accepted", code);
+        }
+    }
+
+    #[test]
+    fn contrast_of_black_and_white_is_the_maximum() {
+        assert_close(contrast_ratio(0x000000, 0xffffff), 21.0);
+        assert_close(contrast_ratio(0xffffff, 0x000000), 21.0);
+        assert_close(contrast_ratio(0x6a0dad, 0x6a0dad), 1.0);
+
+        assert_close(luminance(0xffffff), 1.0);
+        assert_close(luminance(0x000000), 0.0);
+    }
+
+    #[test]
+    fn contrast_ratings_follow_wcag() {
+        assert_eq!(contrast_rating(21.0), "AAA");
+        assert_eq!(contrast_rating(4.5), "AA");
+        assert_eq!(contrast_rating(3.0), "AA Large");
+        assert_eq!(contrast_rating(2.9), "Fail");
+    }
+
+    #[test]
+    fn hsl_and_hsv_of_known_colors() {
+        let hsl = to_hsl(0x00ff00);
+        assert_close(hsl.h, 120.0);
+        assert_close(hsl.s, 1.0);
+        assert_close(hsl.l, 0.5);
+
+        let hsv = to_hsv(0x808080);
+        assert_close(hsv.s, 0.0);
+        assert_close(hsv.v, 128.0 / 255.0);
+    }
+
+    #[test]
+    fn hsl_and_hsv_round_trip() {
+        for color in [0x000000, 0xffffff, 0xff0000, 0x00ff00, 0x0000ff, 0xff7518, 0x6a0dad, 0x313338, 0x123456] {
+            assert_eq!(from_hsl(to_hsl(color)), color, "hsl {:06x}", color);
+            assert_eq!(from_hsv(to_hsv(color)), color, "hsv {:06x}", color);
+        }
+    }
+
+    #[test]
+    fn cmyk_of_known_colors() {
+        assert_eq!(to_cmyk(0x000000), (0.0, 0.0, 0.0, 1.0));
+        assert_eq!(to_cmyk(0xff0000), (0.0, 1.0, 1.0, 0.0));
+        assert_eq!(to_cmyk(0xffffff), (0.0, 0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn lab_of_known_colors() {
+        let white = to_lab(0xffffff);
+        assert!((white.l - 100.0).abs() < 0.1 && white.a.abs() < 0.1 && white.b.abs() < 0.1);
+
+        let black = to_lab(0x000000);
+        assert_close(black.l, 0.0);
+
+        assert_close(delta_e(0xff7518, 0xff7518), 0.0);
+        assert!(delta_e(0x000000, 0xffffff) > 99.0);
+    }
+
+    #[test]
+    fn css_names_are_found() {
+        let (name, distance) = nearest_css_name(0xff0000);
+        assert_eq!(name, "red");
+        assert_close(distance, 0.0);
+
+        assert_eq!(nearest_css_name(0x663398).0, "rebeccapurple");
+    }
+
+    #[test]
+    fn hue_rotation_and_complement() {
+        assert_eq!(complement(0xff0000), 0x00ffff);
+        assert_eq!(complement(0x00ffff), 0xff0000);
+        assert_eq!(rotate_hue(0xff0000, 120.0), 0x00ff00);
+        assert_eq!(rotate_hue(0xff0000, -120.0), 0x0000ff);
+        assert_eq!(complement(0x808080), 0x808080);
+    }
+
+    #[test]
+    fn random_colors_are_repeatable_and_distinct() {
+        let first = random_colors(&mut ColorRng::new(42), 5, true);
+        let second = random_colors(&mut ColorRng::new(42), 5, true);
+
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 5);
+        assert!(first.iter().all(|c| *c <= 0xffffff && is_readable(*c)));
+        for (i, a) in first.iter().enumerate() {
+            assert!(first[i + 1..].iter().all(|b| delta_e(*a, *b) >= 20.0));
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use poise::serenity_prelude::{ChannelId, CreateAttachment, CreateEmbed, CreateEmbedFooter, CreateMessage, GuildId, Http, Timestamp, UserId};
+use tracing::info;
+
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::ColorSource;
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::Error;
+
+/// A color change as it gets posted in the log channel of a guild
+pub struct ColorChange {
+    /// Member the color role belongs to
+    pub uid: UserId,
+    /// Member that made the change, None when it was not made through a command - eg a scheduled job
+    pub actor: Option<UserId>,
+    /// Member a stolen color was taken from
+    pub victim: Option<UserId>,
+    /// None when the color role got created
+    pub old_color: Option<u32>,
+    /// None when the color role got removed
+    pub color: Option<u32>,
+    pub source: ColorSource,
+}
+
+impl ColorChange {
+    fn title(&self) -> &'static str {
+        match (self.old_color, self.color, self.source) {
+            (None, _, _) => "Color role created",
+            (_, None, _) => "Color role removed",
+            (_, _, ColorSource::Steal) => "Color stolen",
+            _ => "Color updated",
+        }
+    }
+}
+
+/// Posts the change in the log channel of the guild, if it has one.
+/// A missing channel or permissions never fail the change itself, it only gets logged here.
+pub async fn log_color_change(http: &Http, pool: &DbPool, guild: GuildId, change: ColorChange) -> Result<(), Error> {
+    let hex = |color: Option<u32>| color.map(|c| format!("`#{:06x}`", c)).unwrap_or("-".to_string());
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+
+    let mut embed = CreateEmbed::default()
+        .title(change.title())
+        .field("Member", format!("<@{}>", change.uid), true)
+        .field("By", change.actor.map(|a| format!("<@{}>", a)).unwrap_or("CyberBun".to_string()), true)
+        .field("Source", change.source.as_str(), true)
+        .field("Before", hex(change.old_color), true)
+        .field("After", hex(change.color), true)
+        .image("attachment://swatch.png")
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    if let Some(victim) = change.victim {
+        embed = embed.field("Stolen from", format!("<@{}>", victim), true);
+    }
+
+    if let Some(color) = change.color.or(change.old_color) {
+        embed = embed.color(color);
+    }
+
+    let swatch = swatch_png(change.old_color, change.color);
+    let message = CreateMessage::new()
+        .embed(embed)
+        .add_file(CreateAttachment::bytes(swatch, "swatch.png"));
+
+    post_in_log_channel(http, pool, guild, message).await
+}
+
+/// Sends the message to the log channel of the guild, does nothing when it has none
+pub async fn post_in_log_channel(http: &Http, pool: &DbPool, guild: GuildId, message: CreateMessage) -> Result<(), Error> {
+    let settings = GuildSettingsRepo::new(&mut pool.acquire().await?).get_color_settings(guild.into()).await?;
+    let log_channel = match settings.and_then(|s| s.log_channel) {
+        Some(channel) => ChannelId::from(channel),
+        None => return Ok(()),
+    };
+
+    if let Err(err) = log_channel.send_message(http, message).await {
+        info!("Could not post in the log channel of guild {} - {}", guild, err);
+    }
+
+    Ok(())
+}
+
+const SWATCH_WIDTH: usize = 128;
+const SWATCH_HEIGHT: usize = 32;
+
+/// A small image with the old color on the left and the new one on the right.
+/// Removed or created colors leave their half transparent.
+fn swatch_png(old: Option<u32>, new: Option<u32>) -> Vec<u8> {
+    let pixel = |color: Option<u32>| match color {
+        Some(c) => [(c >> 16) as u8, (c >> 8) as u8, c as u8, 255],
+        None => [0, 0, 0, 0],
+    };
+    let (left, right) = (pixel(old), pixel(new));
+
+    // Every row starts with filter type 0 followed by RGBA pixels
+    let mut row = vec![0];
+    for x in 0..SWATCH_WIDTH {
+        row.extend_from_slice(if x < SWATCH_WIDTH / 2 { &left } else { &right });
+    }
+    let raw = row.repeat(SWATCH_HEIGHT);
+
+    let mut zlib = ZlibEncoder::new(vec![], Compression::default());
+    zlib.write_all(&raw).expect("Error, writing to memory failed");
+    let zlib = zlib.finish().expect("Error, writing to memory failed");
+
+    let mut header = vec![];
+    header.extend_from_slice(&(SWATCH_WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(SWATCH_HEIGHT as u32).to_be_bytes());
+    // 8 bit depth, RGBA, default compression, filtering and no interlacing
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+    png_chunk(&mut png, b"IHDR", &header);
+    png_chunk(&mut png, b"IDAT", &zlib);
+    png_chunk(&mut png, b"IEND", &[]);
+
+    png
+}
+
+fn png_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+    png.extend_from_slice(&crc.finalize().to_be_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+
+    use super::*;
+
+    #[test]
+    fn swatch_decodes_to_both_colors() {
+        let png = swatch_png(Some(0x112233), None);
+        assert_eq!(&png[..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+
+        let mut chunks = vec![];
+        let mut rest = &png[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+
+            assert_eq!(crc, crc32fast::hash(&rest[4..8 + len]));
+            chunks.push((kind.to_vec(), data.to_vec()));
+            rest = &rest[12 + len..];
+        }
+
+        let kinds: Vec<&[u8]> = chunks.iter().map(|(kind, _)| kind.as_slice()).collect();
+        assert_eq!(kinds, vec![b"IHDR".as_slice(), b"IDAT", b"IEND"]);
+        assert_eq!(chunks[0].1, [&128u32.to_be_bytes()[..], &32u32.to_be_bytes(), &[8, 6, 0, 0, 0]].concat());
+
+        let mut raw = vec![];
+        ZlibDecoder::new(chunks[1].1.as_slice()).read_to_end(&mut raw).unwrap();
+
+        let stride = 1 + SWATCH_WIDTH * 4;
+        assert_eq!(raw.len(), stride * SWATCH_HEIGHT);
+        for row in raw.chunks(stride) {
+            assert_eq!(row[0], 0);
+            assert_eq!(&row[1..5], &[0x11, 0x22, 0x33, 255]);
+            assert_eq!(&row[stride - 4..], &[0, 0, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn steals_get_their_own_title() {
+        let change = ColorChange {
+            uid: UserId::new(1),
+            actor: Some(UserId::new(1)),
+            victim: Some(UserId::new(2)),
+            old_color: Some(0),
+            color: Some(1),
+            source: ColorSource::Steal,
+        };
+
+        assert_eq!(change.title(), "Color stolen");
+    }
+}
@@ -0,0 +1,118 @@
+use poise::serenity_prelude::{EditRole, GuildId, Http, Timestamp, UserId};
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+use super::hierarchy::describe_error;
+use super::is_not_found;
+use crate::color_math::parse_hex;
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_cycle_repo::{ColorCycle, ColorCycleRepo};
+use crate::db_handlers::color_repo::{ColorRepo, ColorSource};
+use crate::Error;
+
+/// Role edits the cycles get per minute, across all guilds together.
+/// Kept well below discord's limits since role edits share them with everything else the bot does.
+pub const EDITS_PER_MINUTE: u64 = 20;
+/// Time between two cycle steps, spreading the budget evenly over the minute
+pub const CYCLE_TICK: Duration = Duration::from_millis(60_000 / EDITS_PER_MINUTE);
+/// Shortest interval a member can pick
+pub const MIN_INTERVAL: u64 = 5 * 60;
+pub const MAX_COLORS: usize = 10;
+
+/// Moves the cycle that is due the longest one color further, at most one role edit per call
+pub async fn run_next_cycle(http: &Http, pool: &DbPool) -> Result<(), Error> {
+    let Some(cycle) = ColorCycleRepo::new(&mut pool.acquire().await?).get_due(1).await?.pop() else {
+        return Ok(());
+    };
+
+    let colors = cycle_colors(&cycle);
+    let color_role = ColorRepo::new(&mut pool.acquire().await?).get(cycle.uid, cycle.guid).await?;
+
+    let Some(color_role) = color_role.filter(|_| !colors.is_empty()) else {
+        // Nothing left to cycle
+        ColorCycleRepo::new(&mut pool.acquire().await?).stop(cycle.uid, cycle.guid).await?;
+        return Ok(());
+    };
+
+    let step = cycle.step as usize % colors.len();
+    let color = colors[step];
+
+    // Marked first, so the role update event does not take this for a change made in the server settings
+    ColorRepo::new(&mut pool.acquire().await?).set_pending_color(color_role.role_id, Some(color)).await?;
+
+    let guild = GuildId::from(cycle.guid);
+    let next_step = match guild.edit_role(http, color_role.role_id, EditRole::new().colour(color)).await {
+        Ok(_) => {
+            // Only a color that made it onto the role is stored
+            let mut conn = pool.acquire().await?;
+            let mut repo = ColorRepo::new(&mut conn);
+            repo.update_color(color_role.role_id, color).await?;
+            repo.add_history(cycle.uid, cycle.guid, Some(color_role.color), color, ColorSource::Cycle).await?;
+            (step + 1) % colors.len()
+        }
+        Err(err) => {
+            ColorRepo::new(&mut pool.acquire().await?).set_pending_color(color_role.role_id, None).await?;
+
+            // The role is gone or out of reach, stop instead of failing every step
+            if is_not_found(&err) || describe_error(&err.into()).is_some() {
+                info!("Stopped color cycle of user {} in guild {}", cycle.uid, guild);
+
+                ColorCycleRepo::new(&mut pool.acquire().await?).stop(cycle.uid, cycle.guid).await?;
+                return Ok(());
+            }
+
+            warn!("Color cycle step of user {} failed, trying the same color again next interval", cycle.uid);
+            step
+        }
+    };
+
+    // Counted from now so a backlog after downtime does not turn into a burst of edits
+    let next_run = (Timestamp::now().unix_timestamp() + cycle.interval) as u64;
+    ColorCycleRepo::new(&mut pool.acquire().await?)
+        .advance(cycle.uid, cycle.guid, next_step as u32, next_run)
+        .await?;
+
+    Ok(())
+}
+
+/// Stops the cycle of a member and gives them back the color they had before it started.
+/// Returns false when no cycle was running.
+pub async fn stop_member_cycle(http: &Http, pool: &DbPool, guild: GuildId, uid: UserId) -> Result<bool, Error> {
+    let (uid, guid) = (uid.into(), guild.into());
+
+    let Some(cycle) = ColorCycleRepo::new(&mut pool.acquire().await?).get(uid, guid).await? else {
+        return Ok(false);
+    };
+
+    ColorCycleRepo::new(&mut pool.acquire().await?).stop(uid, guid).await?;
+
+    if let Some(color_role) = ColorRepo::new(&mut pool.acquire().await?).get(uid, guid).await? {
+        ColorRepo::new(&mut pool.acquire().await?).set_pending_color(color_role.role_id, Some(cycle.base_color)).await?;
+
+        // A role that is gone gets recreated with the stored color, so that still goes back to the base color
+        match guild.edit_role(http, color_role.role_id, EditRole::new().colour(cycle.base_color)).await {
+            Ok(_) => {}
+            Err(err) if is_not_found(&err) => {}
+            Err(err) => {
+                ColorRepo::new(&mut pool.acquire().await?).set_pending_color(color_role.role_id, None).await?;
+                return Err(err.into());
+            }
+        }
+
+        let mut conn = pool.acquire().await?;
+        let mut repo = ColorRepo::new(&mut conn);
+        repo.update_color(color_role.role_id, cycle.base_color).await?;
+        if color_role.color != cycle.base_color {
+            repo.add_history(uid, guid, Some(color_role.color), cycle.base_color, ColorSource::Cycle).await?;
+        }
+    }
+
+    Ok(true)
+}
+
+fn cycle_colors(cycle: &ColorCycle) -> Vec<u32> {
+    cycle.colors
+        .split(',')
+        .filter_map(|c| parse_hex(c).ok())
+        .collect()
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use poise::serenity_prelude::{Member, Timestamp};
+
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::ColorRepo;
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::db_handlers::ids::DbRoleId;
+use crate::Error;
+
+/// Why a member is not allowed to have a color role
+pub enum Ineligible {
+    Denied,
+    MissingRole(Vec<DbRoleId>),
+    TooNew { eligible_at: i64 },
+    NotBooster,
+}
+
+impl fmt::Display for Ineligible {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ineligible::Denied => {
+                write!(f, "You are not allowed to have a color role on this server")
+            }
+            Ineligible::MissingRole(roles) => {
+                let roles: Vec<String> = roles.iter().map(|r| format!("<@&{}>", r)).collect();
+                write!(f, "You need one of these roles to get a color: {}", roles.join(", "))
+            }
+            Ineligible::TooNew { eligible_at } => {
+                write!(f, "You have not been on this server long enough, you can get a color <t:{}:R>", eligible_at)
+            }
+            Ineligible::NotBooster => {
+                write!(f, "Color roles are only for server boosters here")
+            }
+        }
+    }
+}
+
+/// Checks the guild's rules for who can have a color role, None means the member is eligible
+pub async fn check_eligibility(pool: &DbPool, member: &Member) -> Result<Option<Ineligible>, Error> {
+    let guid = member.guild_id.into();
+    let mut conn = pool.acquire().await?;
+
+    let denied = ColorRepo::new(&mut conn).denied_users(guid).await?;
+    if denied.contains(&member.user.id.into()) {
+        return Ok(Some(Ineligible::Denied));
+    }
+
+    let required = ColorRepo::new(&mut conn).required_roles(guid).await?;
+    if !required.is_empty() && !member.roles.iter().any(|r| required.contains(&(*r).into())) {
+        return Ok(Some(Ineligible::MissingRole(required)));
+    }
+
+    let Some(settings) = GuildSettingsRepo::new(&mut conn).get_color_settings(guid).await? else {
+        return Ok(None);
+    };
+
+    if settings.min_member_age > 0 {
+        let joined_at = member.joined_at.map(|j| j.unix_timestamp()).unwrap_or_default();
+        let eligible_at = joined_at + settings.min_member_age as i64 * 24 * 60 * 60;
+
+        if eligible_at > Timestamp::now().unix_timestamp() {
+            return Ok(Some(Ineligible::TooNew { eligible_at }));
+        }
+    }
+
+    if settings.booster_only && member.premium_since.is_none() {
+        return Ok(Some(Ineligible::NotBooster));
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+
+use poise::serenity_prelude::prelude::SerenityError;
+use poise::serenity_prelude::{GuildId, Http, HttpError, Permissions, RoleId};
+
+use crate::Error;
+
+/// Why the bot can't manage the color roles of a guild
+pub enum RoleProblem {
+    MissingManageRoles,
+    /// The color role is at or above the highest role of the bot
+    RoleAboveBot { role_id: RoleId },
+}
+
+impl fmt::Display for RoleProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoleProblem::MissingManageRoles => write!(
+                f,
+                "I need the **Manage Roles** permission to handle color roles, ask an admin to give it to me"
+            ),
+            RoleProblem::RoleAboveBot { role_id } => write!(
+                f,
+                "The color role <@&{}> is above my highest role so I can't change it, ask an admin to move my role above it",
+                role_id
+            ),
+        }
+    }
+}
+
+/// Checks that the bot can create, edit and assign the color role before anything is changed.
+/// An anchor role above the bot is no problem, the color roles then go as high as the bot can place them.
+/// None means everything is in order.
+pub async fn preflight(http: &Http, guild: GuildId, color_role: Option<RoleId>) -> Result<Option<RoleProblem>, Error> {
+    let bot = guild.current_user_member(http).await?;
+    let roles = guild.roles(http).await?;
+
+    // The @everyone role shares its id with the guild
+    let mut permissions = roles.get(&RoleId::new(guild.get()))
+        .map(|r| r.permissions)
+        .unwrap_or_default();
+    let mut top_position = 0;
+    for role in bot.roles.iter().filter_map(|id| roles.get(id)) {
+        permissions |= role.permissions;
+        top_position = top_position.max(role.position);
+    }
+
+    if !permissions.intersects(Permissions::MANAGE_ROLES | Permissions::ADMINISTRATOR) {
+        return Ok(Some(RoleProblem::MissingManageRoles));
+    }
+
+    if let Some(role) = color_role.and_then(|id| roles.get(&id)) {
+        if role.position >= top_position {
+            return Ok(Some(RoleProblem::RoleAboveBot { role_id: role.id }));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Turns the errors discord gives for role changes into something the user or an admin can act on.
+/// None for errors that are not about roles.
+pub fn describe_error(err: &Error) -> Option<&'static str> {
+    let code = match err.downcast_ref::<SerenityError>() {
+        Some(SerenityError::Http(HttpError::UnsuccessfulRequest(res))) => res.error.code,
+        _ => return None,
+    };
+
+    // https://discord.com/developers/docs/topics/opcodes-and-status-codes#json
+    match code {
+        10007 => Some("That member is no longer on this server"),
+        10011 => Some("The color role no longer exists, try again to have it recreated"),
+        30005 => Some("This server has reached the maximum of 250 roles, an admin has to remove some roles first"),
+        50001 | 50013 => Some(
+            "I'm not allowed to change that role - I need the **Manage Roles** permission and my highest role has to be above the color roles, ask an admin to fix this"
+        ),
+        _ => None,
+    }
+}
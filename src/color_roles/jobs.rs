@@ -0,0 +1,170 @@
+use poise::serenity_prelude::Http;
+use tracing::{info, warn};
+
+use super::set_member_color;
+use crate::color_math::parse_hex;
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_job_repo::{ColorJob, ColorJobRepo, JobKind};
+use crate::db_handlers::color_repo::{ColorRepo, ColorRow, ColorSource};
+use crate::Error;
+
+/// Parses durations like `1d`, `2h30m` or `45m` into seconds, a plain number is taken as minutes
+pub fn parse_duration(input: &str) -> Option<u64> {
+    let input = input.trim().to_lowercase();
+
+    if let Ok(minutes) = input.parse::<u64>() {
+        return minutes.checked_mul(60).filter(|secs| *secs > 0);
+    }
+
+    let mut total: u64 = 0;
+    let mut number = String::new();
+
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'w' => 7 * 24 * 60 * 60,
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            ' ' => continue,
+            _ => return None,
+        };
+
+        let value: u64 = number.parse().ok()?;
+        total = total.checked_add(value.checked_mul(unit)?)?;
+        number.clear();
+    }
+
+    // Trailing number without a unit, or nothing at all
+    if !number.is_empty() || total == 0 {
+        return None;
+    }
+
+    Some(total)
+}
+
+/// Runs every job that is due, a job that fails is logged and dropped so it does not fail forever
+pub async fn run_due_jobs(http: &Http, pool: &DbPool) -> Result<(), Error> {
+    let jobs = ColorJobRepo::new(&mut pool.acquire().await?).get_due().await?;
+
+    for job in jobs.iter() {
+        let result = match job.kind {
+            JobKind::Revert => revert(http, pool, job).await,
+            JobKind::Theme => apply_theme(http, pool, job).await.map(|failed| {
+                if failed > 0 {
+                    warn!("Color theme {} could not be applied to {} member(s)", job.id, failed);
+                }
+            }),
+        };
+
+        if let Err(err) = result {
+            warn!("Color job {} failed - {}", job.id, err);
+        }
+
+        ColorJobRepo::new(&mut pool.acquire().await?).delete(job.id).await?;
+    }
+
+    Ok(())
+}
+
+async fn revert(http: &Http, pool: &DbPool, job: &ColorJob) -> Result<(), Error> {
+    let (Some(uid), Some(color)) = (job.uid, job.color) else {
+        return Ok(());
+    };
+
+    // The color role might have been removed in the meantime
+    if let Some(color_role) = ColorRepo::new(&mut pool.acquire().await?).get(uid, job.guid).await? {
+        set_member_color(http, pool, job.guid.into(), &color_role, color, None, ColorSource::Scheduled).await?;
+        info!("Reverted temporary color of user {}", uid);
+    }
+
+    Ok(())
+}
+
+/// Gives every color role in the guild a theme color, a member that can't be changed is logged and skipped.
+/// Returns the amount of members that were skipped.
+async fn apply_theme(http: &Http, pool: &DbPool, job: &ColorJob) -> Result<usize, Error> {
+    let colors: Vec<u32> = job.theme
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|c| parse_hex(c).ok())
+        .collect();
+
+    if colors.is_empty() {
+        return Ok(0);
+    }
+
+    let revert_at = (job.run_at + job.duration.unwrap_or_default()) as u64;
+
+    let color_roles = ColorRepo::new(&mut pool.acquire().await?).get_guild(job.guid).await?;
+
+    let mut failed = 0;
+
+    // Hand out the theme colors in turns
+    for (i, color_role) in color_roles.iter().enumerate() {
+        if let Err(err) = apply_theme_color(http, pool, job, color_role, colors[i % colors.len()], revert_at).await {
+            warn!("Could not apply color theme {} to user {} - {}", job.id, color_role.uid, err);
+            failed += 1;
+        }
+    }
+
+    info!("Applied color theme {} to {} color role(s)", job.id, color_roles.len() - failed);
+
+    Ok(failed)
+}
+
+async fn apply_theme_color(http: &Http, pool: &DbPool, job: &ColorJob, color_role: &ColorRow, color: u32, revert_at: u64) -> Result<(), Error> {
+    ColorJobRepo::new(&mut pool.acquire().await?)
+        .schedule_revert(color_role.uid, job.guid, color_role.color, revert_at)
+        .await?;
+
+    set_member_color(http, pool, job.guid.into(), color_role, color, None, ColorSource::Scheduled).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_with_units() {
+        assert_eq!(parse_duration("1h30m"), Some(90 * 60));
+        assert_eq!(parse_duration("1H 30M"), Some(90 * 60));
+        assert_eq!(parse_duration("2w"), Some(14 * 24 * 60 * 60));
+        assert_eq!(parse_duration("1d12h"), Some(36 * 60 * 60));
+        assert_eq!(parse_duration("45s"), Some(45));
+    }
+
+    #[test]
+    fn bare_numbers_are_minutes() {
+        assert_eq!(parse_duration("15"), Some(15 * 60));
+        assert_eq!(parse_duration(" 90 "), Some(90 * 60));
+    }
+
+    #[test]
+    fn zero_is_no_duration() {
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("0h0m"), None);
+    }
+
+    #[test]
+    fn overflow_is_rejected() {
+        assert_eq!(parse_duration(&u64::MAX.to_string()), None);
+        assert_eq!(parse_duration("99999999999999999999w"), None);
+        assert_eq!(parse_duration(&format!("{}w", u64::MAX / 60)), None);
+        assert_eq!(parse_duration(&format!("{}s{}s", u64::MAX, 1)), None);
+    }
+
+    #[test]
+    fn junk_is_rejected() {
+        for input in ["", "   ", "soon", "1x", "h", "1h30", "-5m", "1.5h", "1h-30m"] {
+            assert_eq!(parse_duration(input), None, "{:?} was accepted", input);
+        }
+    }
+}
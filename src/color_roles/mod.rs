@@ -0,0 +1,169 @@
+use poise::serenity_prelude::prelude::SerenityError;
+use poise::serenity_prelude::{EditRole, GuildId, Http, HttpError, Member, RoleId, UserId};
+use tracing::info;
+
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::{ColorRepo, ColorRow, ColorSource};
+use crate::db_handlers::ids::DbRoleId;
+use crate::Error;
+
+use audit_log::{log_color_change, ColorChange};
+
+pub mod audit_log;
+pub mod cycle;
+pub mod eligibility;
+pub mod hierarchy;
+pub mod jobs;
+pub mod naming;
+pub mod positioning;
+pub mod reconcile;
+pub mod transfer;
+
+/// Deletes the color role from the guild and the database, a role that was already deleted is simply skipped
+pub async fn remove_color_role(
+    http: &Http,
+    pool: &DbPool,
+    guild: GuildId,
+    color_role: &ColorRow,
+    actor: Option<UserId>,
+    source: ColorSource,
+) -> Result<(), Error> {
+    match guild.delete_role(http, color_role.role_id).await {
+        Ok(_) => {}
+        Err(err) if is_not_found(&err) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    ColorRepo::new(&mut pool.acquire().await?).delete(color_role.role_id).await?;
+
+    info!("Removed color role {} of user {}", color_role.role_id, color_role.uid);
+
+    let change = ColorChange { uid: color_role.uid.into(), actor, victim: None, old_color: Some(color_role.color), color: None, source };
+    log_color_change(http, pool, guild, change).await?;
+
+    Ok(())
+}
+
+/// Creates a new role for a color role that no longer exists in the guild and points the database to it
+pub async fn recreate_color_role(http: &Http, pool: &DbPool, guild: GuildId, old_role_id: DbRoleId, name: &str, color: u32) -> Result<RoleId, Error> {
+    let new_role = EditRole::new()
+        .name(name)
+        .hoist(false)
+        .mentionable(false)
+        .colour(color);
+
+    let role = guild.create_role(http, new_role).await?;
+
+    let mut conn = pool.acquire().await?;
+    let mut repo = ColorRepo::new(&mut conn);
+    repo.update_role_id(old_role_id, role.id.into()).await?;
+    // Not every caller knows the old role is gone, `/color audit` cleans it up if it is not
+    repo.abandon_role(old_role_id, guild.into()).await?;
+    drop(conn);
+
+    positioning::position_color_role(http, pool, guild, role.id).await?;
+
+    Ok(role.id)
+}
+
+/// Gives a returning member their color role back, recreating it if it got deleted while they were gone
+pub async fn restore_color_role(http: &Http, pool: &DbPool, member: &Member, color_role: &ColorRow) -> Result<(), Error> {
+    match member.add_role(http, color_role.role_id).await {
+        Ok(_) => return Ok(()),
+        Err(err) if is_not_found(&err) => {}
+        Err(err) => return Err(err.into()),
+    }
+
+    let role_id = recreate_color_role(http, pool, member.guild_id, color_role.role_id, &color_role.role_name, color_role.color).await?;
+    member.add_role(http, role_id).await?;
+
+    info!("Restored color role of returning user {}", member.user.id);
+
+    Ok(())
+}
+
+/// Changes the color of an existing color role outside of a command, eg from a scheduled job.
+/// Members that left only get the database updated, their role is recreated with it when they return.
+pub async fn set_member_color(
+    http: &Http,
+    pool: &DbPool,
+    guild: GuildId,
+    color_role: &ColorRow,
+    color: u32,
+    actor: Option<UserId>,
+    source: ColorSource,
+) -> Result<(), Error> {
+    let mem = match guild.member(http, color_role.uid).await {
+        Ok(mem) => Some(mem),
+        Err(err) if is_not_found(&err) => None,
+        Err(err) => return Err(err.into()),
+    };
+
+    let changed: Result<(), Error> = async {
+        let mut role_id = color_role.role_id;
+        let mut name = color_role.role_name.clone();
+
+        if let Some(mem) = mem {
+            // The name can contain the color code
+            name = naming::role_name_for(pool, guild, &mem.user, mem.nick.as_deref(), color).await?;
+
+            // Marked first, so the role update event does not take this for a change made in the server settings
+            ColorRepo::new(&mut pool.acquire().await?).set_pending_color(role_id, Some(color)).await?;
+
+            match guild.edit_role(http, role_id, EditRole::new().name(&name).colour(color)).await {
+                Ok(_) => {}
+                Err(err) if is_not_found(&err) => {
+                    let new_role = recreate_color_role(http, pool, guild, role_id, &name, color).await?;
+                    mem.add_role(http, new_role).await?;
+                    role_id = new_role.into();
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // Only stored once discord accepted the change
+        let mut conn = pool.acquire().await?;
+        let mut repo = ColorRepo::new(&mut conn);
+        repo.update_color(role_id, color).await?;
+        if name != color_role.role_name {
+            repo.update_name(role_id, name).await?;
+        }
+        repo.add_history(color_role.uid, guild.into(), Some(color_role.color), color, source).await?;
+
+        Ok(())
+    }.await;
+
+    // A pending color left behind would make the bot ignore the next change to that color made in the server settings
+    if let Err(err) = changed {
+        ColorRepo::new(&mut pool.acquire().await?).clear_pending_color(color_role.uid, guild.into()).await?;
+        return Err(err);
+    }
+
+    let change = ColorChange { uid: color_role.uid.into(), actor, victim: None, old_color: Some(color_role.color), color: Some(color), source };
+    log_color_change(http, pool, guild, change).await?;
+
+    Ok(())
+}
+
+/// Joins the lines up to the given length, noting how many had to be left out
+pub fn truncate_lines(lines: &[String], max: usize) -> String {
+    let mut out = String::new();
+
+    for (i, line) in lines.iter().enumerate() {
+        if out.len() + line.len() + 1 > max - 20 {
+            out.push_str(&format!("...and {} more", lines.len() - i));
+            break;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    out
+}
+
+/// True when discord responded with a 404, eg the role or member does not exist (anymore)
+pub fn is_not_found(err: &SerenityError) -> bool {
+    matches!(err,
+        SerenityError::Http(HttpError::UnsuccessfulRequest(res)) if res.status_code.as_u16() == 404
+    )
+}
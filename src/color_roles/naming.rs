@@ -0,0 +1,133 @@
+use poise::serenity_prelude::{EditRole, GuildId, Http, User};
+use tracing::info;
+
+use super::is_not_found;
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::ColorRepo;
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::db_handlers::ids::DbRoleId;
+use crate::Error;
+
+/// Used when a guild has not configured its own template - matches how roles were always named
+pub const DEFAULT_TEMPLATE: &str = "{username}";
+
+const PLACEHOLDERS: [&str; 3] = ["{username}", "{display_name}", "{hex}"];
+
+/// Discord does not allow role names longer than this
+const MAX_ROLE_NAME: usize = 100;
+
+/// Checks that the template only uses known placeholders, returns what is wrong with it otherwise
+pub fn validate_template(template: &str) -> Result<(), String> {
+    if template.trim().is_empty() {
+        return Err("The template can not be empty".to_string());
+    }
+
+    // Blank out the known placeholders, any braces left over belong to unknown ones
+    let mut rest = template.to_string();
+    for p in PLACEHOLDERS.iter() {
+        rest = rest.replace(p, "");
+    }
+
+    if rest.contains('{') || rest.contains('}') {
+        return Err(format!("Unknown placeholder, you can use: {}", PLACEHOLDERS.join(", ")));
+    }
+
+    Ok(())
+}
+
+/// Fills in the placeholders of the template
+pub fn render_role_name(template: &str, username: &str, display_name: &str, color: u32) -> String {
+    let name = template
+        .replace("{username}", username)
+        .replace("{display_name}", display_name)
+        .replace("{hex}", &format!("{:06x}", color));
+
+    name.chars().take(MAX_ROLE_NAME).collect()
+}
+
+/// The name the color role of this member should have in the given guild
+pub async fn role_name_for(pool: &DbPool, guild: GuildId, user: &User, nick: Option<&str>, color: u32) -> Result<String, Error> {
+    let template = GuildSettingsRepo::new(&mut pool.acquire().await?)
+        .get_color_settings(guild.into())
+        .await?
+        .and_then(|s| s.naming_template)
+        .unwrap_or(DEFAULT_TEMPLATE.to_string());
+
+    let display_name = nick
+        .or(user.global_name.as_deref())
+        .unwrap_or(&user.name);
+
+    Ok(render_role_name(&template, &user.name, display_name, color))
+}
+
+/// Renames the color role of a member when their name changed, returns true when it got renamed
+pub async fn sync_role_name(http: &Http, pool: &DbPool, guild: GuildId, user: &User, nick: Option<&str>) -> Result<bool, Error> {
+    let color_role = match ColorRepo::new(&mut pool.acquire().await?).get(user.id.into(), guild.into()).await? {
+        Some(color_role) => color_role,
+        None => return Ok(false),
+    };
+
+    let name = role_name_for(pool, guild, user, nick, color_role.color).await?;
+
+    if name == color_role.role_name {
+        return Ok(false);
+    }
+
+    if !rename_color_role(http, pool, guild, color_role.role_id, name).await? {
+        return Ok(false);
+    }
+
+    info!("Renamed color role of user {}", user.id);
+
+    Ok(true)
+}
+
+/// Gives the color role its new name, returns false when the role is gone.
+/// The naming template is what decides the name - a role renamed in the server settings gets renamed back by the audit as well.
+pub async fn rename_color_role(http: &Http, pool: &DbPool, guild: GuildId, role_id: DbRoleId, name: String) -> Result<bool, Error> {
+    // A missing role gets recreated (with the stored name) by the audit or when the member sets a color again
+    match guild.edit_role(http, role_id, EditRole::new().name(&name)).await {
+        Ok(_) => {}
+        Err(err) if is_not_found(&err) => return Ok(false),
+        Err(err) => return Err(err.into()),
+    }
+
+    ColorRepo::new(&mut pool.acquire().await?).update_name(role_id, name).await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_with_known_placeholders_are_valid() {
+        assert!(validate_template(DEFAULT_TEMPLATE).is_ok());
+        assert!(validate_template("{display_name} - {hex}").is_ok());
+        assert!(validate_template("no placeholders").is_ok());
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        assert!(validate_template("   ").is_err());
+        assert!(validate_template("{nickname}").is_err());
+        assert!(validate_template("{username").is_err());
+        assert!(validate_template("username}").is_err());
+    }
+
+    #[test]
+    fn placeholders_are_filled_in() {
+        let name = render_role_name("{username} ({display_name}) #{hex}", "bun", "Bunny", 0x00ff0a);
+
+        assert_eq!(name, "bun (Bunny) #00ff0a");
+    }
+
+    #[test]
+    fn rendered_names_fit_discord() {
+        let long = "ü".repeat(80);
+        let name = render_role_name("{username}{display_name}", &long, &long, 0);
+
+        assert_eq!(name.chars().count(), MAX_ROLE_NAME);
+    }
+}
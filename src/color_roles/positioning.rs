@@ -0,0 +1,138 @@
+use poise::serenity_prelude::{GuildId, Http, LightMethod, Request, Role, RoleId, Route};
+use serde_json::json;
+use tracing::info;
+
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::Error;
+
+/// Moves the given color roles directly under the anchor role, or as high as the bot's own top role allows.
+/// All roles are reordered with a single request, returns the amount of roles that changed position.
+pub async fn reposition_color_roles(http: &Http, guild: GuildId, managed: &[RoleId], anchor: RoleId) -> Result<usize, Error> {
+    let bot_id = http.get_current_user().await?.id;
+    let bot = guild.member(http, bot_id).await?;
+    let roles = guild.roles(http).await?;
+
+    // The bot can only move roles that are below its own highest role
+    let bot_top = bot.roles.iter()
+        .filter_map(|r| roles.get(r))
+        .map(|r| r.position)
+        .max()
+        .unwrap_or(0);
+
+    let roles: Vec<&Role> = roles.values().collect();
+    let changes = reordered_positions(&roles, guild, bot_top, managed, anchor);
+
+    if changes.is_empty() {
+        return Ok(0);
+    }
+
+    let body: Vec<_> = changes.iter()
+        .map(|(id, position)| json!({ "id": id, "position": position }))
+        .collect();
+
+    let request = Request::new(Route::GuildRoles { guild_id: guild }, LightMethod::Patch)
+        .body(Some(serde_json::to_vec(&body)?));
+    http.request(request).await?;
+
+    info!("Repositioned {} color role(s)", changes.len());
+
+    Ok(changes.len())
+}
+
+/// Works out the new order for the roles below the bot's top role so the managed roles sit directly under the anchor,
+/// or right under the bot's top role when the anchor is above it or gone.
+/// The positions in use are handed out again in the new order, only roles that actually move are returned.
+fn reordered_positions(roles: &[&Role], guild: GuildId, bot_top: u16, managed: &[RoleId], anchor: RoleId) -> Vec<(RoleId, u16)> {
+    // Leave out @everyone, which always has the same ID as the guild
+    let mut sorted: Vec<&Role> = roles.iter()
+        .copied()
+        .filter(|r| r.position < bot_top && r.id.get() != guild.get())
+        .collect();
+    sorted.sort_by_key(|r| (r.position, r.id));
+
+    let positions: Vec<u16> = sorted.iter().map(|r| r.position).collect();
+
+    let (moved, mut order): (Vec<&Role>, Vec<&Role>) = sorted
+        .into_iter()
+        .partition(|r| managed.contains(&r.id));
+
+    let at = order.iter().position(|r| r.id == anchor).unwrap_or(order.len());
+    order.splice(at..at, moved);
+
+    order.iter()
+        .zip(positions)
+        .filter(|(r, position)| r.position != *position)
+        .map(|(r, position)| (r.id, position))
+        .collect()
+}
+
+/// Keeps a freshly created or edited color role under the anchor role, if the guild has one set
+pub async fn position_color_role(http: &Http, pool: &DbPool, guild: GuildId, role_id: RoleId) -> Result<(), Error> {
+    let anchor = GuildSettingsRepo::new(&mut pool.acquire().await?)
+        .get_color_settings(guild.into())
+        .await?
+        .and_then(|s| s.anchor_role);
+
+    if let Some(anchor) = anchor {
+        reposition_color_roles(http, guild, &[role_id], anchor.into()).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    fn role(id: u64, position: u16) -> Role {
+        let mut role = Role::default();
+        role.id = RoleId::new(id);
+        role.position = position;
+        role
+    }
+
+    fn moves(roles: &[Role], bot_top: u16, managed: &[u64], anchor: u64) -> Vec<(u64, u16)> {
+        let roles: Vec<&Role> = roles.iter().collect();
+        let managed: Vec<RoleId> = managed.iter().map(|&id| RoleId::new(id)).collect();
+
+        let mut moves: Vec<(u64, u16)> = reordered_positions(&roles, GUILD, bot_top, &managed, RoleId::new(anchor))
+            .into_iter()
+            .map(|(id, position)| (id.get(), position))
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn managed_roles_go_right_under_the_anchor() {
+        // @everyone, color 10, color 11, other 20, anchor 30, other 40, bot 50
+        let roles = [role(1, 0), role(10, 1), role(11, 2), role(20, 3), role(30, 4), role(40, 5), role(50, 6)];
+
+        assert_eq!(moves(&roles, 6, &[10, 11], 30), vec![(10, 2), (11, 3), (20, 1)]);
+
+        // Already in place, nothing to move
+        let roles = [role(1, 0), role(20, 1), role(10, 2), role(11, 3), role(30, 4), role(50, 5)];
+        assert!(moves(&roles, 5, &[10, 11], 30).is_empty());
+    }
+
+    #[test]
+    fn anchor_above_the_bot_clamps_under_the_bots_top_role() {
+        // The anchor at 5 is out of reach, so the color role goes as high as the bot can put it
+        let roles = [role(1, 0), role(10, 1), role(20, 2), role(21, 3), role(50, 4), role(30, 5)];
+
+        assert_eq!(moves(&roles, 4, &[10], 30), vec![(10, 3), (20, 1), (21, 2)]);
+    }
+
+    #[test]
+    fn missing_anchor_puts_the_roles_at_the_top() {
+        let roles = [role(1, 0), role(10, 1), role(20, 2), role(50, 3)];
+
+        assert_eq!(moves(&roles, 3, &[10], 99), vec![(10, 2), (20, 1)]);
+
+        // Roles above the bot and @everyone are never touched
+        assert!(moves(&roles, 3, &[1, 50], 99).is_empty());
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use poise::serenity_prelude::{Colour, CreateEmbed, CreateEmbedFooter, CreateMessage, GuildId, Http, Role, RoleId, Timestamp};
+use tracing::info;
+
+use super::audit_log::{log_color_change, post_in_log_channel, ColorChange};
+use super::naming::{rename_color_role, role_name_for};
+use super::{is_not_found, recreate_color_role, truncate_lines};
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::{ColorRepo, ColorRow, ColorSource};
+use crate::db_handlers::ids::{DbRoleId, DbUserId};
+use crate::Error;
+
+/// A difference between the colors table and the actual roles in a guild
+pub enum Drift {
+    /// The role in the database no longer exists in the guild
+    MissingRole { uid: DbUserId, role_id: DbRoleId, color: u32, role_name: String },
+    /// A color role the bot replaced with a new one, but that is still in the guild
+    OrphanedRole { role_id: RoleId, role_name: String },
+    /// The color of the role was changed outside of the bot
+    ColorMismatch { uid: DbUserId, role_id: DbRoleId, stored: u32, actual: u32 },
+    /// The role was renamed outside of the bot
+    StaleName { role_id: DbRoleId, stored: String, actual: String },
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::MissingRole { uid, role_name, .. } => {
+                write!(f, "Missing role `{}` of <@{}>", role_name, uid)
+            }
+            Drift::OrphanedRole { role_id, role_name } => {
+                write!(f, "Orphaned role `{}` (<@&{}>)", role_name, role_id)
+            }
+            Drift::ColorMismatch { role_id, stored, actual, .. } => {
+                write!(f, "<@&{}> is {} but stored as {}", role_id, Colour::new(*actual).hex(), Colour::new(*stored).hex())
+            }
+            Drift::StaleName { role_id, stored, actual } => {
+                write!(f, "<@&{}> is named `{}` but stored as `{}`", role_id, actual, stored)
+            }
+        }
+    }
+}
+
+/// Compares the colors table of a guild with its actual roles
+pub async fn find_drift(http: &Http, pool: &DbPool, guild: GuildId) -> Result<Vec<Drift>, Error> {
+    let mut conn = pool.acquire().await?;
+    let mut repo = ColorRepo::new(&mut conn);
+    let colors = repo.get_guild(guild.into()).await?;
+    let abandoned = repo.get_abandoned_roles(guild.into()).await?;
+
+    // Nothing to compare against - skip the request for the roles
+    if colors.is_empty() && abandoned.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let roles = guild.roles(http).await?;
+
+    // Replaced roles that are gone already need no cleanup anymore
+    for role_id in abandoned.iter().filter(|r| !roles.contains_key(&(**r).into())) {
+        repo.forget_abandoned_role(*role_id).await?;
+    }
+
+    Ok(detect_drift(&colors, &abandoned, &roles))
+}
+
+/// Only roles the bot replaced itself count as orphaned, a role that just looks like a color role could be one an admin made
+fn detect_drift(colors: &[ColorRow], abandoned: &[DbRoleId], roles: &HashMap<RoleId, Role>) -> Vec<Drift> {
+    let mut drift = vec![];
+
+    for color in colors.iter() {
+        match roles.get(&color.role_id.into()) {
+            Some(role) => {
+                if role.colour.0 != color.color {
+                    drift.push(Drift::ColorMismatch { uid: color.uid, role_id: color.role_id, stored: color.color, actual: role.colour.0 });
+                }
+                if role.name != color.role_name {
+                    drift.push(Drift::StaleName { role_id: color.role_id, stored: color.role_name.clone(), actual: role.name.clone() });
+                }
+            }
+            None => drift.push(Drift::MissingRole {
+                uid: color.uid,
+                role_id: color.role_id,
+                color: color.color,
+                role_name: color.role_name.clone(),
+            }),
+        }
+    }
+
+    for role_id in abandoned.iter() {
+        let managed = colors.iter().any(|c| c.role_id == *role_id);
+
+        if let (false, Some(role)) = (managed, roles.get(&(*role_id).into())) {
+            drift.push(Drift::OrphanedRole { role_id: role.id, role_name: role.name.clone() });
+        }
+    }
+
+    drift
+}
+
+/// Posts the drift in the log channel of the guild without changing anything - only an admin running the audit repairs it
+pub async fn report_drift(http: &Http, pool: &DbPool, guild: GuildId, drift: &[Drift]) -> Result<(), Error> {
+    let lines: Vec<String> = drift.iter().map(|d| format!("- {}", d)).collect();
+    let footer = CreateEmbedFooter::new("Use /color audit with repair to fix them");
+    let embed = CreateEmbed::default()
+        .title("Color roles out of sync")
+        .description(truncate_lines(&lines, 4000))
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    post_in_log_channel(http, pool, guild, CreateMessage::new().embed(embed)).await
+}
+
+/// Fixes the given drift - the guild is treated as the source of truth for colors, the naming template for names,
+/// missing roles get recreated and orphaned ones deleted.
+pub async fn repair_drift(http: &Http, pool: &DbPool, guild: GuildId, drift: &[Drift]) -> Result<(), Error> {
+    for d in drift.iter() {
+        match d {
+            Drift::MissingRole { uid, role_id, color, role_name } => {
+                // Members that left get their role recreated once they rejoin
+                let mem = match guild.member(http, *uid).await {
+                    Ok(mem) => mem,
+                    Err(err) if is_not_found(&err) => continue,
+                    Err(err) => return Err(err.into()),
+                };
+
+                let new_role = recreate_color_role(http, pool, guild, *role_id, role_name, *color).await?;
+                mem.add_role(http, new_role).await?;
+            }
+            Drift::OrphanedRole { role_id, .. } => {
+                match guild.delete_role(http, *role_id).await {
+                    Ok(_) => {}
+                    Err(err) if is_not_found(&err) => {}
+                    Err(err) => return Err(err.into()),
+                }
+
+                ColorRepo::new(&mut pool.acquire().await?).forget_abandoned_role((*role_id).into()).await?;
+            }
+            Drift::ColorMismatch { uid, role_id, stored, actual } => {
+                ColorRepo::new(&mut pool.acquire().await?).update_color(*role_id, *actual).await?;
+                ColorRepo::new(&mut pool.acquire().await?)
+                    .add_history(*uid, guild.into(), Some(*stored), *actual, ColorSource::Admin)
+                    .await?;
+
+                let change = ColorChange { uid: (*uid).into(), actor: None, victim: None, old_color: Some(*stored), color: Some(*actual), source: ColorSource::Admin };
+                log_color_change(http, pool, guild, change).await?;
+            }
+            Drift::StaleName { role_id, .. } => {
+                // Named after the template again, the same way a member changing their name does
+                let Some(color_role) = ColorRepo::new(&mut pool.acquire().await?).get_by_role(*role_id).await? else {
+                    continue;
+                };
+                let mem = match guild.member(http, color_role.uid).await {
+                    Ok(mem) => mem,
+                    Err(err) if is_not_found(&err) => continue,
+                    Err(err) => return Err(err.into()),
+                };
+
+                let name = role_name_for(pool, guild, &mem.user, mem.nick.as_deref(), color_role.color).await?;
+                rename_color_role(http, pool, guild, *role_id, name).await?;
+            }
+        }
+    }
+
+    info!("Repaired {} color role difference(s) in guild {}", drift.len(), guild);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::ids::DbGuildId;
+
+    fn color(role_id: u64, uid: u64, color: u32, name: &str) -> ColorRow {
+        ColorRow { role_id: DbRoleId(role_id), uid: DbUserId(uid), guid: DbGuildId(10), color, role_name: name.to_string() }
+    }
+
+    fn role(id: u64, color: u32, name: &str) -> (RoleId, Role) {
+        let mut role = Role::default();
+        role.id = RoleId::new(id);
+        role.colour = Colour::new(color);
+        role.name = name.to_string();
+
+        (role.id, role)
+    }
+
+    #[test]
+    fn roles_in_sync_have_no_drift() {
+        let colors = [color(100, 1, 0xff0000, "bun")];
+        let roles = HashMap::from([role(100, 0xff0000, "bun")]);
+
+        assert!(detect_drift(&colors, &[], &roles).is_empty());
+    }
+
+    #[test]
+    fn every_kind_of_drift_is_found() {
+        let colors = [color(100, 1, 0xff0000, "bun"), color(200, 2, 0x00ff00, "hop")];
+        let roles = HashMap::from([role(100, 0x0000ff, "renamed"), role(300, 0x00ff00, "hop")]);
+
+        let drift = detect_drift(&colors, &[DbRoleId(300)], &roles);
+
+        assert_eq!(drift.len(), 4);
+        assert!(matches!(drift[0], Drift::ColorMismatch { role_id: DbRoleId(100), stored: 0xff0000, actual: 0x0000ff, .. }));
+        assert!(matches!(&drift[1], Drift::StaleName { role_id: DbRoleId(100), actual, .. } if actual == "renamed"));
+        assert!(matches!(drift[2], Drift::MissingRole { role_id: DbRoleId(200), .. }));
+        assert!(matches!(drift[3], Drift::OrphanedRole { role_id, .. } if role_id == RoleId::new(300)));
+    }
+
+    #[test]
+    fn lookalike_roles_are_not_orphaned() {
+        // Made by an admin - same name and color, no permissions, but the bot never owned it
+        let colors = [color(100, 1, 0xff0000, "bun")];
+        let roles = HashMap::from([role(100, 0xff0000, "bun"), role(300, 0xff0000, "bun")]);
+
+        assert!(detect_drift(&colors, &[], &roles).is_empty());
+    }
+
+    #[test]
+    fn abandoned_roles_only_count_while_they_exist_and_are_unused() {
+        let colors = [color(100, 1, 0xff0000, "bun")];
+        let roles = HashMap::from([role(100, 0xff0000, "bun")]);
+
+        // One is gone already, the other one is in use again
+        assert!(detect_drift(&colors, &[DbRoleId(100), DbRoleId(300)], &roles).is_empty());
+    }
+}
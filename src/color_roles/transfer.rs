@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use poise::serenity_prelude::{EditRole, GuildId, Http, Member, RoleId, UserId};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::naming::role_name_for;
+use super::positioning::position_color_role;
+use super::audit_log::{log_color_change, ColorChange};
+use super::hierarchy::describe_error;
+use super::{is_not_found, set_member_color};
+use crate::color_math::parse_hex;
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::{ColorRepo, ColorSource};
+use crate::db_handlers::ids::DbUserId;
+use crate::Error;
+
+/// A single color as it is exported, ids are strings so they survive tools that read numbers as floats
+#[derive(Serialize, Deserialize)]
+pub struct ExportedColor {
+    pub user_id: String,
+    pub color: String,
+    pub role_name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ColorExport {
+    pub guild_id: String,
+    pub colors: Vec<ExportedColor>,
+}
+
+const CSV_HEADER: &str = "user_id,color,role_name";
+
+pub async fn export_colors(pool: &DbPool, guild: GuildId) -> Result<ColorExport, Error> {
+    let colors = ColorRepo::new(&mut pool.acquire().await?).get_guild(guild.into()).await?;
+
+    Ok(ColorExport {
+        guild_id: guild.to_string(),
+        colors: colors.iter()
+            .map(|c| ExportedColor {
+                user_id: c.uid.to_string(),
+                color: format!("#{:06x}", c.color),
+                role_name: c.role_name.clone(),
+            })
+            .collect(),
+    })
+}
+
+pub fn to_csv(export: &ColorExport) -> String {
+    let mut out = format!("{}\n", CSV_HEADER);
+
+    for c in export.colors.iter() {
+        // Role names are free text, quote them and double any quotes inside
+        out.push_str(&format!("{},{},\"{}\"\n", c.user_id, c.color, c.role_name.replace('"', "\"\"")));
+    }
+
+    out
+}
+
+/// Reads an export back in, JSON or CSV is detected from the content
+pub fn parse_export(data: &str) -> Result<Vec<ExportedColor>, String> {
+    let data = data.trim_start_matches('\u{feff}').trim();
+
+    if data.starts_with('{') {
+        return serde_json::from_str::<ColorExport>(data)
+            .map(|e| e.colors)
+            .map_err(|err| format!("Invalid JSON: {}", err));
+    }
+
+    let mut colors = vec![];
+    for (i, line) in data.lines().enumerate() {
+        if line.trim().is_empty() || (i == 0 && line.trim() == CSV_HEADER) {
+            continue;
+        }
+
+        let fields = split_csv_line(line);
+        match fields.as_slice() {
+            [user_id, color, role_name] => colors.push(ExportedColor {
+                user_id: user_id.clone(),
+                color: color.clone(),
+                role_name: role_name.clone(),
+            }),
+            _ => return Err(format!("Line {} should have 3 fields: {}", i + 1, CSV_HEADER)),
+        }
+    }
+
+    Ok(colors)
+}
+
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+/// What importing a single color would do
+pub enum ImportAction {
+    Create { uid: u64, color: u32, role_name: String },
+    Update { uid: u64, color: u32 },
+    Unchanged { uid: u64 },
+    NotInGuild { uid: u64 },
+    Invalid { line: usize, reason: String },
+}
+
+impl fmt::Display for ImportAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportAction::Create { uid, color, .. } => write!(f, "Create `#{:06x}` for <@{}>", color, uid),
+            ImportAction::Update { uid, color } => write!(f, "Update <@{}> to `#{:06x}`", uid, color),
+            ImportAction::Unchanged { uid } => write!(f, "Unchanged <@{}>", uid),
+            ImportAction::NotInGuild { uid } => write!(f, "Skipped <@{}> - not on this server", uid),
+            ImportAction::Invalid { line, reason } => write!(f, "Skipped entry {} - {}", line, reason),
+        }
+    }
+}
+
+/// Works out what importing the colors would change, without changing anything
+pub async fn plan_import(http: &Http, pool: &DbPool, guild: GuildId, colors: &[ExportedColor]) -> Result<Vec<ImportAction>, Error> {
+    let mut plan = vec![];
+
+    // A member listed more than once gets the last of their colors
+    let last_lines: HashMap<u64, usize> = colors.iter()
+        .enumerate()
+        .filter_map(|(i, c)| c.user_id.trim().parse::<u64>().ok().map(|uid| (uid, i + 1)))
+        .collect();
+
+    for (i, c) in colors.iter().enumerate() {
+        let line = i + 1;
+
+        let Ok(uid) = c.user_id.trim().parse::<u64>() else {
+            plan.push(ImportAction::Invalid { line, reason: format!("invalid user id `{}`", c.user_id) });
+            continue;
+        };
+        if let Some(last) = last_lines.get(&uid).filter(|last| **last != line) {
+            plan.push(ImportAction::Invalid { line, reason: format!("<@{}> is listed again in entry {}", uid, last) });
+            continue;
+        }
+        let Ok(color) = parse_hex(&c.color) else {
+            plan.push(ImportAction::Invalid { line, reason: format!("invalid color `{}`", c.color) });
+            continue;
+        };
+        if uid == 0 {
+            plan.push(ImportAction::Invalid { line, reason: "invalid user id `0`".to_string() });
+            continue;
+        }
+
+        match guild.member(http, uid).await {
+            Ok(_) => {}
+            Err(err) if is_not_found(&err) => {
+                plan.push(ImportAction::NotInGuild { uid });
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let action = match ColorRepo::new(&mut pool.acquire().await?).get(DbUserId(uid), guild.into()).await? {
+            Some(existing) if existing.color == color => ImportAction::Unchanged { uid },
+            Some(_) => ImportAction::Update { uid, color },
+            None => ImportAction::Create { uid, color, role_name: c.role_name.clone() },
+        };
+        plan.push(action);
+    }
+
+    Ok(plan)
+}
+
+/// The creates and updates of an import that went through, and the members that failed with why
+#[derive(Default)]
+pub struct ImportResult {
+    pub applied: usize,
+    pub failed: Vec<(u64, String)>,
+}
+
+/// Carries out the creates and updates of an import plan, actor is the admin that started the import.
+/// A member that fails is skipped so the rest of the import still goes through.
+pub async fn apply_import(http: &Http, pool: &DbPool, guild: GuildId, plan: &[ImportAction], actor: UserId) -> ImportResult {
+    let mut result = ImportResult::default();
+
+    for action in plan.iter() {
+        let (uid, applied) = match action {
+            ImportAction::Create { uid, color, role_name } => (*uid, import_create(http, pool, guild, *uid, *color, role_name, actor).await),
+            ImportAction::Update { uid, color } => (*uid, import_update(http, pool, guild, *uid, *color, actor).await),
+            _ => continue,
+        };
+
+        match applied {
+            Ok(()) => result.applied += 1,
+            Err(err) => {
+                let reason = describe_error(&err).map(str::to_string).unwrap_or(err.to_string());
+                warn!("Could not import the color of user {} into guild {} - {}", uid, guild, reason);
+                result.failed.push((uid, reason));
+            }
+        }
+    }
+
+    info!("Imported {} color(s) into guild {}, {} failed", result.applied, guild, result.failed.len());
+
+    result
+}
+
+async fn import_create(http: &Http, pool: &DbPool, guild: GuildId, uid: u64, color: u32, role_name: &str, actor: UserId) -> Result<(), Error> {
+    let mem = guild.member(http, UserId::new(uid)).await?;
+
+    // Fall back to this guild's naming template when the export has no name
+    let name = match role_name.trim().is_empty() {
+        true => role_name_for(pool, guild, &mem.user, mem.nick.as_deref(), color).await?,
+        false => role_name.to_string(),
+    };
+
+    let new_role = EditRole::new()
+        .name(&name)
+        .hoist(false)
+        .mentionable(false)
+        .colour(color);
+    let role = guild.create_role(http, new_role).await?;
+
+    // The role only stays when the member got it and the database points to it
+    if let Err(err) = finish_import_create(http, pool, &mem, role.id, color, name).await {
+        match guild.delete_role(http, role.id).await {
+            Ok(_) => info!("Deleted color role {} after a failed import", role.id),
+            Err(err) => warn!("Could not delete color role {} after a failed import - {}", role.id, err),
+        }
+        return Err(err);
+    }
+
+    let change = ColorChange { uid: mem.user.id, actor: Some(actor), victim: None, old_color: None, color: Some(color), source: ColorSource::Admin };
+    log_color_change(http, pool, guild, change).await
+}
+
+async fn finish_import_create(http: &Http, pool: &DbPool, mem: &Member, role_id: RoleId, color: u32, name: String) -> Result<(), Error> {
+    let guild = mem.guild_id;
+
+    position_color_role(http, pool, guild, role_id).await?;
+    mem.add_role(http, role_id).await?;
+
+    let mut tx = pool.begin().await?;
+    let saved: Result<(), Error> = async {
+        let mut repo = ColorRepo::new(&mut tx);
+        repo.create(role_id.into(), mem.user.id.into(), guild.into(), color, name).await?;
+        repo.add_history(mem.user.id.into(), guild.into(), None, color, ColorSource::Admin).await?;
+
+        Ok(())
+    }.await;
+
+    match saved {
+        Ok(()) => tx.commit().await,
+        Err(err) => tx.rollback().await.and(Err(err)),
+    }
+}
+
+async fn import_update(http: &Http, pool: &DbPool, guild: GuildId, uid: u64, color: u32, actor: UserId) -> Result<(), Error> {
+    let Some(color_role) = ColorRepo::new(&mut pool.acquire().await?).get(DbUserId(uid), guild.into()).await? else {
+        return Err("the color role was removed in the meantime".into());
+    };
+
+    set_member_color(http, pool, guild, &color_role, color, Some(actor), ColorSource::Admin).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn export(colors: &[(&str, &str, &str)]) -> ColorExport {
+        ColorExport {
+            guild_id: "10".to_string(),
+            colors: colors.iter()
+                .map(|(user_id, color, role_name)| ExportedColor {
+                    user_id: user_id.to_string(),
+                    color: color.to_string(),
+                    role_name: role_name.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn fields(colors: &[ExportedColor]) -> Vec<(&str, &str, &str)> {
+        colors.iter().map(|c| (c.user_id.as_str(), c.color.as_str(), c.role_name.as_str())).collect()
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let colors = [
+            ("1", "#ff7518", "plain"),
+            ("2", "#6a0dad", "with, commas, inside"),
+            ("3", "#000000", "a \"quoted\" name"),
+            ("4", "#ffffff", ""),
+        ];
+
+        let csv = to_csv(&export(&colors));
+
+        assert!(csv.starts_with(CSV_HEADER));
+        assert_eq!(fields(&parse_export(&csv).unwrap()), colors);
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let colors = [("1", "#ff7518", "with, commas"), ("2", "#6a0dad", "{braces}")];
+
+        let json = serde_json::to_string_pretty(&export(&colors)).unwrap();
+
+        assert_eq!(fields(&parse_export(&json).unwrap()), colors);
+        assert!(parse_export("{ not json").is_err());
+    }
+
+    #[test]
+    fn csv_header_and_blank_lines_are_optional() {
+        let with_header = "\u{feff}user_id,color,role_name\r\n1,#ff0000,red\r\n\r\n2,#00ff00,\"green, really\"\r\n";
+        let without_header = "1,#ff0000,red\n2,#00ff00,\"green, really\"";
+
+        let expected = [("1", "#ff0000", "red"), ("2", "#00ff00", "green, really")];
+        assert_eq!(fields(&parse_export(with_header).unwrap()), expected);
+        assert_eq!(fields(&parse_export(without_header).unwrap()), expected);
+    }
+
+    #[test]
+    fn csv_lines_with_the_wrong_field_count_are_rejected() {
+        let err = parse_export("user_id,color,role_name\n1,#ff0000\n").err().unwrap();
+
+        assert!(err.starts_with("Line 2"), "{}", err);
+        assert!(parse_export("1,#ff0000,red,extra").is_err());
+    }
+
+    #[test]
+    fn csv_fields_split_on_unquoted_commas() {
+        assert_eq!(split_csv_line("a,b,c"), ["a", "b", "c"]);
+        assert_eq!(split_csv_line("a,\"b,c\",d"), ["a", "b,c", "d"]);
+        assert_eq!(split_csv_line("\"say \"\"hi\"\"\",,"), ["say \"hi\"", "", ""]);
+    }
+}
@@ -1,14 +1,35 @@
 use std::collections::HashMap;
 
-use super::super::db_handlers::color_handlers::*;
+use super::super::color_roles::audit_log::{log_color_change, ColorChange};
+use super::super::color_roles::cycle::{stop_member_cycle, MAX_COLORS, MIN_INTERVAL};
+use super::super::color_roles::eligibility::check_eligibility;
+use super::super::color_roles::hierarchy::{describe_error, preflight};
+use super::super::color_roles::jobs::parse_duration;
+use super::super::color_roles::naming::{render_role_name, role_name_for, sync_role_name, validate_template, DEFAULT_TEMPLATE};
+use super::super::color_roles::positioning::{position_color_role, reposition_color_roles};
+use super::super::color_roles::reconcile::{find_drift, repair_drift};
+use super::super::color_roles::transfer::{apply_import, export_colors, parse_export, plan_import, to_csv, ImportAction, ImportResult};
+use super::super::color_roles::{is_not_found, remove_color_role, truncate_lines};
+use super::super::color_math::{
+    complement as complement_of, contrast_rating, contrast_ratio, nearest_css_name, parse_hex, random_colors, rotate_hue, to_cmyk,
+    to_hsl, to_hsv, ColorRng, DISCORD_DARK, DISCORD_LIGHT,
+};
+use super::super::db_handlers::color_cycle_repo::ColorCycleRepo;
+use super::super::db_handlers::color_job_repo::ColorJobRepo;
+use super::super::db_handlers::color_repo::{ColorHistory, ColorRepo, ColorRow, ColorSource, StealProtection};
+use super::super::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use super::super::db_handlers::ids::DbRoleId;
 use super::{Context, Error};
 use poise::serenity_prelude::{
-    Color, Colour, CreateAttachment, CreateEmbed, CreateEmbedFooter, EditRole, Http, Member, Mentionable, Role, RoleId, Timestamp, User
+    Attachment, ButtonStyle, Channel, Colour, ComponentInteractionCollector, CreateActionRow, CreateAttachment, CreateButton, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, EditRole, GuildId, Http, Member, Mentionable, Role, RoleId, Timestamp, User, UserId
 };
-use poise::CreateReply;
+use poise::{ChoiceParameter, CreateReply};
 use tokio::fs::File;
+use tokio::time::Duration;
+use tracing::{info, warn};
 
-#[poise::command(slash_command, subcommands("set", "info", "steal", "help"))]
+#[poise::command(slash_command, subcommands("set", "info", "steal", "help", "remove", "reset", "purge", "anchor", "reposition", "audit", "leave_cleanup", "log_channel", "history", "undo", "protect", "steal_settings", "naming", "random", "complement", "harmony", "theme", "cycle", "eligibility", "inspect", "export", "import"))]
 pub async fn color(_ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
@@ -22,13 +43,12 @@ pub async fn help(ctx: Context<'_>) -> Result<(), Error> {
     // Check if user already has a color role
     let user = ctx.author();
     let guild = ctx.guild_id().unwrap();
-    let mem = guild.member(&ctx.http(), user.id.get()).await?;
-    let conn = ctx.data().pool.acquire().await?;
+    let mem = guild.member(&ctx.http(), user.id).await?;
 
-    if let Some(col) = get_color(conn, user.id.get(), guild.get()).await? {
+    if let Some(col) = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(user.id.into(), guild.into()).await? {
         // If user does - display help embed ; listing other roles that might take color priority
         let bot_avatar =  ctx.http().get_current_user().await?.avatar_url().unwrap_or("".to_string());
-        let embed = help_embed(&mem, col.role_id, &ctx.http(), bot_avatar).await?;
+        let embed = help_embed(&mem, col.role_id, ctx.http(), bot_avatar).await?;
         ctx.send(CreateReply::default().embed(embed)).await?;
     } else {
         // No color found
@@ -43,9 +63,10 @@ pub async fn help(ctx: Context<'_>) -> Result<(), Error> {
 pub async fn set(
     ctx: Context<'_>,
     #[description = "Color code - hexadecimal (eg. 0xffaa99 | #11ffaa)"] color: String,
+    #[description = "Only use this color for a while, then switch back (eg. 1d | 2h30m | 45m)"] duration: Option<String>,
 ) -> Result<(), Error> {
     // Convert hex string to int
-    let color = match u32::from_str_radix(&color.replace("0x", "").replace("#", ""), 16) {
+    let color = match parse_hex(&color) {
         Ok(val) => val,
         Err(err) => {
             ctx.reply(format!("Invalid color code? err : {}", err))
@@ -54,11 +75,228 @@ pub async fn set(
         }
     };
 
-    insert_or_update_color(ctx, color).await?;
+    let uid = ctx.author().id.into();
+    let guid = ctx.guild_id().unwrap().into();
+
+    let duration = match duration.as_deref().map(parse_duration) {
+        Some(Some(duration)) => Some(duration),
+        Some(None) => {
+            ctx.reply("Invalid duration, use something like `1d`, `2h30m` or `45m`").await?;
+            return Ok(());
+        }
+        None => None,
+    };
+
+    match duration {
+        Some(duration) => {
+            // Temporary colors switch back to the color the user has now
+            if ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(uid, guid).await?.is_none() {
+                ctx.reply("Set a normal color first, so there is something to switch back to").await?;
+                return Ok(());
+            }
+
+            let revert_at = Timestamp::now().unix_timestamp() as u64 + duration;
+
+            if insert_or_update_color(ctx, color, Some(ColorSource::Set), Some(revert_at), None).await? {
+                ctx.reply(format!("Your color switches back <t:{}:R>", revert_at)).await?;
+            }
+        }
+        None => {
+            insert_or_update_color(ctx, color, Some(ColorSource::Set), None, None).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Let your color cycle through a list of colors - for server boosters
+#[poise::command(slash_command, subcommands("cycle_start", "cycle_stop"), guild_only)]
+pub async fn cycle(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Start cycling through colors, replaces any cycle you already had running
+#[poise::command(slash_command, rename = "start", ephemeral, guild_only)]
+pub async fn cycle_start(
+    ctx: Context<'_>,
+    #[description = "Color codes separated by commas (eg. #ff7518, #6a0dad)"] colors: String,
+    #[description = "Time between colors, at least 5 minutes (Default = 10m)"] interval: Option<String>,
+) -> Result<(), Error> {
+    let uid = ctx.author().id;
+    let guid = ctx.guild_id().unwrap();
+
+    let mem = guid.member(ctx.http(), uid).await?;
+    if mem.premium_since.is_none() {
+        ctx.reply("Cycling colors are a perk for server boosters").await?;
+        return Ok(());
+    }
+
+    if let Some(reason) = check_eligibility(&ctx.data().pool, &mem).await? {
+        ctx.reply(reason.to_string()).await?;
+        return Ok(());
+    }
+
+    let parsed: Result<Vec<u32>, _> = colors.split(',').map(parse_hex).collect();
+    let parsed = match parsed {
+        Ok(parsed) if (2..=MAX_COLORS).contains(&parsed.len()) => parsed,
+        _ => {
+            ctx.reply(format!("Give 2 to {} color codes separated by commas (eg. #ff7518, #6a0dad)", MAX_COLORS)).await?;
+            return Ok(());
+        }
+    };
+
+    let interval = match parse_duration(interval.as_deref().unwrap_or("10m")) {
+        Some(interval) if interval >= MIN_INTERVAL => interval,
+        _ => {
+            ctx.reply("Invalid interval, use something like `10m` or `1h` - at least 5 minutes").await?;
+            return Ok(());
+        }
+    };
+
+    // Cycles change the existing role, so there has to be one - its color is what you go back to when stopping
+    let Some(color_role) = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(uid.into(), guid.into()).await? else {
+        ctx.reply("Set a normal color first with `/color set`").await?;
+        return Ok(());
+    };
+
+    if let Some(problem) = preflight(ctx.http(), guid, Some(color_role.role_id.into())).await? {
+        ctx.reply(problem.to_string()).await?;
+        return Ok(());
+    }
+
+    let cycle: Vec<String> = parsed.iter().map(|c| format!("{:06x}", c)).collect();
+
+    ColorCycleRepo::new(&mut ctx.data().pool.acquire().await?)
+        .start(uid.into(), guid.into(), cycle.join(","), interval, color_role.color)
+        .await?;
+
+    ctx.reply(format!(
+        "Your color now cycles through {} colors, switching every {} minute(s)",
+        parsed.len(),
+        interval / 60
+    )).await?;
+
+    Ok(())
+}
+
+/// Stop cycling colors and go back to the color you had before
+#[poise::command(slash_command, rename = "stop", ephemeral, guild_only)]
+pub async fn cycle_stop(ctx: Context<'_>) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    match stop_member_cycle(ctx.http(), &ctx.data().pool, guid, ctx.author().id).await? {
+        true => { ctx.reply("Your color stopped cycling").await?; }
+        false => { ctx.reply("Your color is not cycling").await?; }
+    }
+
+    Ok(())
+}
+
+/// Schedule color themes for the whole server
+#[poise::command(slash_command, subcommands("theme_schedule", "theme_list", "theme_cancel"), guild_only)]
+pub async fn theme(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Schedule a color theme - every color role gets one of the colors for a while
+#[poise::command(slash_command, rename = "schedule", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn theme_schedule(
+    ctx: Context<'_>,
+    #[description = "Color codes separated by commas (eg. #ff7518, #6a0dad)"] colors: String,
+    #[description = "How long the theme lasts (eg. 1d | 2h30m | 45m)"] duration: String,
+    #[description = "When the theme starts (eg. 1d | 2h30m | 45m) leave blank to start right away"] starts_in: Option<String>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    let parsed: Result<Vec<u32>, _> = colors.split(',').map(parse_hex).collect();
+    let parsed = match parsed {
+        Ok(parsed) if !parsed.is_empty() => parsed,
+        _ => {
+            ctx.reply("Invalid color codes, separate them with commas (eg. #ff7518, #6a0dad)").await?;
+            return Ok(());
+        }
+    };
+
+    let starts_in = match starts_in.as_deref().map(parse_duration) {
+        Some(Some(starts_in)) => starts_in,
+        Some(None) => {
+            ctx.reply("Invalid start, use something like `1d`, `2h30m` or `45m`").await?;
+            return Ok(());
+        }
+        None => 0,
+    };
+
+    let Some(duration) = parse_duration(&duration) else {
+        ctx.reply("Invalid duration, use something like `1d`, `2h30m` or `45m`").await?;
+        return Ok(());
+    };
+
+    let theme: Vec<String> = parsed.iter().map(|c| format!("{:06x}", c)).collect();
+    let start = Timestamp::now().unix_timestamp() as u64 + starts_in;
+
+    let id = ColorJobRepo::new(&mut ctx.data().pool.acquire().await?)
+        .schedule_theme(guid, theme.join(","), duration, start)
+        .await?;
+
+    ctx.reply(format!(
+        "Theme #{} scheduled - starts <t:{}:R> and ends <t:{}:R>",
+        id,
+        start,
+        start + duration
+    )).await?;
+
+    Ok(())
+}
+
+/// List the color themes that are scheduled
+#[poise::command(slash_command, rename = "list", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn theme_list(ctx: Context<'_>) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    let themes = ColorJobRepo::new(&mut ctx.data().pool.acquire().await?).get_guild_themes(guid).await?;
+
+    if themes.is_empty() {
+        ctx.reply("No color themes scheduled").await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = themes.iter()
+        .map(|t| format!(
+            "#{} - `{}` starts <t:{}:R> for {} minute(s)",
+            t.id,
+            t.theme.as_deref().unwrap_or_default(),
+            t.run_at,
+            t.duration.unwrap_or_default() / 60
+        ))
+        .collect();
+
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let embed = CreateEmbed::default()
+        .title("Color themes")
+        .description(lines.join("\n"))
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
 
     Ok(())
 }
 
+/// Cancel a scheduled color theme
+#[poise::command(slash_command, rename = "cancel", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn theme_cancel(
+    ctx: Context<'_>,
+    #[description = "The number of the theme, see `/color theme list`"] id: i64,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    match ColorJobRepo::new(&mut ctx.data().pool.acquire().await?).cancel_theme(guid, id).await? {
+        true => { ctx.reply(format!("Theme #{} cancelled", id)).await?; }
+        false => { ctx.reply("No scheduled theme found with that number").await?; }
+    }
+
+    Ok(())
+}
 
 /// Color info on a given person or yourself if none given
 #[poise::command(slash_command, guild_only)]
@@ -72,29 +310,33 @@ pub async fn info(
         None => ctx.author().clone(),
     };
 
-    let conn = ctx.data().pool.acquire().await?;
+    let guild = ctx.guild_id().unwrap();
+    let roles = guild.roles(&ctx.http()).await?;
 
-    let color_role =
-        get_color(conn, user.id.into(), ctx.guild_id().unwrap().into()).await?;
+    let color_role = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(user.id.into(), guild.into()).await?;
 
-    match color_role {
-        Some(role) => {
-            let guild = ctx.guild_id().unwrap();
-            let roles = guild.roles(&ctx.http()).await?;
-            let role_id = RoleId::new(role.role_id);
+    // Users without a color managed by me show the color of their highest colored role instead
+    let role = match color_role {
+        Some(color_role) => roles.get(&color_role.role_id.into()),
+        None => {
+            let mem = guild.member(&ctx.http(), user.id).await?;
+            mem.roles.iter()
+                .filter_map(|r| roles.get(r))
+                .filter(|r| r.colour.0 != 0)
+                .max_by_key(|r| r.position)
+        }
+    };
 
-            match roles.get(&role_id) {
-                Some(role) => {
-                    let emb = color_info_embed(role.colour, user).await;
-                    let reply = poise::CreateReply::default().embed(emb);
+    match role {
+        Some(role) => {
+            let emb = color_info_embed(role.colour.0, format!("Color information for {}", user.name))
+                .thumbnail(user.avatar_url().unwrap_or("".to_string()));
+            let reply = poise::CreateReply::default().embed(emb);
 
-                    ctx.send(reply).await?;
-                }
-                None => {}
-            };
+            ctx.send(reply).await?;
         }
         None => {
-            ctx.reply("That user does not seem to have a color role managed by me")
+            ctx.reply("That user does not seem to have a colored role")
                 .await?;
             return Ok(());
         }
@@ -103,6 +345,33 @@ pub async fn info(
     Ok(())
 }
 
+/// Color info on a color code or any role
+#[poise::command(slash_command, guild_only)]
+pub async fn inspect(
+    ctx: Context<'_>,
+    #[description = "Color code - hexadecimal (eg. 0xffaa99 | #11ffaa)"] color: Option<String>,
+    #[description = "The role who's color you want to see"] role: Option<Role>,
+) -> Result<(), Error> {
+    let (color, title) = match (color, role) {
+        (Some(color), _) => match parse_hex(&color) {
+            Ok(color) => (color, format!("Color information for {}", Colour::new(color).hex())),
+            Err(err) => {
+                ctx.reply(format!("Invalid color code? err : {}", err)).await?;
+                return Ok(());
+            }
+        },
+        (None, Some(role)) => (role.colour.0, format!("Color information for {}", role.name)),
+        (None, None) => {
+            ctx.reply("Give a color code or a role to inspect").await?;
+            return Ok(());
+        }
+    };
+
+    let emb = color_info_embed(color, title);
+    ctx.send(CreateReply::default().embed(emb)).await?;
+
+    Ok(())
+}
 
 /// Steal another user's color
 #[poise::command(slash_command, guild_only)]
@@ -113,11 +382,54 @@ pub async fn steal(
     // see if role exists
     // get color code from db
     // set/update personal color role
-    let conn = ctx.data().pool.acquire().await?;
     let uid = user.id;
     let guid = ctx.guild_id().unwrap();
 
-    let color = match get_color(conn, uid.into(), guid.into()).await? {
+    let settings = GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?).get_color_settings(guid.into()).await?;
+    let (steal_enabled, steal_cooldown) = settings
+        .map(|s| (s.steal_enabled, s.steal_cooldown))
+        .unwrap_or((true, 0));
+
+    if !steal_enabled {
+        ctx.reply("Stealing colors is disabled on this server").await?;
+        return Ok(());
+    }
+
+    if uid == ctx.author().id {
+        ctx.reply("You can't steal your own color silly").await?;
+        return Ok(());
+    }
+
+    // Don't bother the target with a steal that can't go through anyway
+    let mem = guid.member(ctx.http(), ctx.author().id).await?;
+    if let Some(reason) = check_eligibility(&ctx.data().pool, &mem).await? {
+        ctx.reply(reason.to_string()).await?;
+        return Ok(());
+    }
+
+    let own_role = ColorRepo::new(&mut ctx.data().pool.acquire().await?)
+        .get(ctx.author().id.into(), guid.into())
+        .await?
+        .map(|c| RoleId::from(c.role_id));
+    if let Some(problem) = preflight(ctx.http(), guid, own_role).await? {
+        ctx.reply(problem.to_string()).await?;
+        return Ok(());
+    }
+
+    // Check if the thief is still on cooldown
+    let mut conn = ctx.data().pool.acquire().await?;
+    let mut repo = ColorRepo::new(&mut conn);
+    let thief = repo.get_member(ctx.author().id.into(), guid.into()).await?;
+    if let Some(last_steal) = thief.and_then(|t| t.last_steal) {
+        let next_steal = last_steal + steal_cooldown as u64;
+
+        if next_steal > Timestamp::now().unix_timestamp() as u64 {
+            ctx.reply(format!("You can steal another color <t:{}:R>", next_steal)).await?;
+            return Ok(());
+        }
+    }
+
+    let color = match repo.get(uid.into(), guid.into()).await? {
         Some(c) => c.color,
         None => {
             ctx.reply("User does not seem to have a color role to steal")
@@ -126,9 +438,32 @@ pub async fn steal(
         }
     };
 
-    insert_or_update_color(ctx, color).await?;
+    let protection = repo.get_member(uid.into(), guid.into())
+        .await?
+        .map(|m| m.steal_protection)
+        .unwrap_or(StealProtection::Off);
+    drop(conn);
+
+    match protection {
+        StealProtection::On => {
+            ctx.reply(format!("{} has protected their color from thieves", user.mention())).await?;
+            return Ok(());
+        }
+        StealProtection::Ask => {
+            if !steal_consent(ctx, &user, color).await? {
+                return Ok(());
+            }
+        }
+        StealProtection::Off => {}
+    }
+
+    if !insert_or_update_color(ctx, color, Some(ColorSource::Steal), None, Some(uid)).await? {
+        return Ok(());
+    }
+
+    ColorRepo::new(&mut ctx.data().pool.acquire().await?).set_last_steal(ctx.author().id.into(), guid.into()).await?;
 
-    let file = File::open("attachments/steal.gif").await?;
+    let file = File::open(&ctx.data().config.steal_gif).await?;
     let attachment = CreateAttachment::file(&file, "steal.gif").await?;
     let reply = CreateReply::default()
         .attachment(attachment)
@@ -143,137 +478,1071 @@ pub async fn steal(
     Ok(())
 }
 
-async fn color_info_embed(color: Color, user: User) -> CreateEmbed {
-    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+/// The choices of `/color protect`
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum ProtectionChoice {
+    #[name = "Off - anyone can steal my color"]
+    Off,
+    #[name = "Ask - I want to accept or decline first"]
+    Ask,
+    #[name = "On - nobody can steal my color"]
+    On,
+}
 
-    let embed = CreateEmbed::default()
-        .title(format!("Color information for {}", user.name))
-        .thumbnail(user.avatar_url().unwrap_or("".to_string()))
-        .color(color)
-        .field("Hex code:", color.hex(), false)
-        .field("R", color.r().to_string(), true)
-        .field("G", color.g().to_string(), true)
-        .field("B", color.b().to_string(), true)
-        .footer(footer)
-        .timestamp(Timestamp::now());
+impl From<ProtectionChoice> for StealProtection {
+    fn from(choice: ProtectionChoice) -> Self {
+        match choice {
+            ProtectionChoice::Off => StealProtection::Off,
+            ProtectionChoice::Ask => StealProtection::Ask,
+            ProtectionChoice::On => StealProtection::On,
+        }
+    }
+}
 
-    return embed;
+/// Protect your color from being stolen
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn protect(
+    ctx: Context<'_>,
+    #[description = "Who can steal your color"] protection: ProtectionChoice,
+) -> Result<(), Error> {
+    let uid = ctx.author().id;
+    let guid = ctx.guild_id().unwrap();
+
+    ColorRepo::new(&mut ctx.data().pool.acquire().await?).set_steal_protection(uid.into(), guid.into(), protection.into()).await?;
+
+    ctx.reply(format!("Steal protection set to: {}", protection.name())).await?;
+
+    Ok(())
 }
 
-async fn help_embed(user: &Member, color_role_id: u64, http: &Http, bot_avatar: String) -> Result<CreateEmbed, Error> {
-    let role_ids = &user.roles;
-    let guild_roles = user.guild_id.roles(http).await?;
-    let color_id = RoleId::from(color_role_id);
+/// Enable / Disable stealing colors and set a cooldown between steals
+#[poise::command(slash_command, rename = "steal-settings", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn steal_settings(
+    ctx: Context<'_>,
+    #[description = "Enable / Disable stealing colors (True = Enabled / False = Disabled)"] enabled: bool,
+    #[description = "Minutes a member has to wait between steals (Default = 0)"] cooldown_minutes: Option<u32>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+    let cooldown_minutes = cooldown_minutes.unwrap_or_default();
 
-    let u_roles: HashMap<&RoleId, &Role>= guild_roles.iter().filter(|r|
-        role_ids.contains(r.0)
-    ).collect();
-    let color_role_pos = u_roles.get(&color_id).unwrap().position;
-    let no_color = Colour::from(0);
+    GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?)
+        .set_steal_settings(guid.into(), enabled, cooldown_minutes.saturating_mul(60))
+        .await?;
 
-                    // - Title / content / false
-    let mut higher: Vec<(String, String, bool)> = vec![];
+    ctx.reply(format!(
+        "Stealing colors is now {} | Cooldown: {} minute(s)",
+        if enabled { "Enabled" } else { "Disabled" },
+        cooldown_minutes
+    )).await?;
 
-    for (_, r) in u_roles.iter() {
-        if r.position.gt(&color_role_pos) && r.colour.0.ne(&no_color.0) {
-            higher.push( (format!("Role: {}", r.name), format!("Color: {}", r.colour.hex()), false) );
+    Ok(())
+}
+
+/// Color harmonies, the hue offsets are in degrees
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum HarmonyScheme {
+    Analogous,
+    Triadic,
+    #[name = "Split-complementary"]
+    SplitComplementary,
+}
+
+impl HarmonyScheme {
+    fn offsets(&self) -> [f64; 3] {
+        match self {
+            HarmonyScheme::Analogous => [0.0, -30.0, 30.0],
+            HarmonyScheme::Triadic => [0.0, 120.0, 240.0],
+            HarmonyScheme::SplitComplementary => [0.0, 150.0, 210.0],
         }
     }
+}
 
-    let footer = CreateEmbedFooter::new("Generated by CyberBun");
-    let embed = CreateEmbed::default()
-    .title("Color - help")
-    .description("
-    Is your custom color not displaying correctly?
-    The following roles if any can be overwriting the color role.
-    Simply set the color for these roles to \"Default\" to fix this issue.
-    ")
-    .thumbnail(bot_avatar)
-    .timestamp(Timestamp::now())
-    .footer(footer)
-    .fields(higher);
+/// A few random colors to pick from - the same seed always gives the same colors
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn random(
+    ctx: Context<'_>,
+    #[description = "Seed for the colors - leave blank for a random one"] seed: Option<u64>,
+    #[description = "Only colors that are readable on both light and dark mode (Default = True)"] readable: Option<bool>,
+) -> Result<(), Error> {
+    let seed = seed.unwrap_or(ctx.id());
+    let readable = readable.unwrap_or(true);
 
-    Ok(embed)
-}
+    let mut rng = ColorRng::new(seed);
+    let colors = random_colors(&mut rng, 5, readable);
 
-async fn insert_or_update_color(ctx: Context<'_>, color: u32) -> Result<(), Error> {
-    let name = ctx.author().name.to_string();
-    let uid = ctx.author().id;
+    let embed = generated_colors_embed(&format!("Random colors - seed {}", seed), &colors);
+    color_buttons(ctx, embed, &colors, ColorSource::Set).await?;
 
-    let guid = match ctx.guild_id() {
-        Some(g) => g,
-        None => {
-            ctx.reply("Can only be used inside a server").await?;
-            return Ok(());
-        }
+    Ok(())
+}
+
+/// The complementary color of a given color or your own
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn complement(
+    ctx: Context<'_>,
+    #[description = "Color code - hexadecimal (eg. 0xffaa99 | #11ffaa) leave blank for your own"] color: Option<String>,
+) -> Result<(), Error> {
+    let base = match base_color(ctx, color).await? {
+        Some(base) => base,
+        None => return Ok(()),
     };
 
-    // see if color exists
-    let conn = ctx.data().pool.acquire().await?;
-    let color_role = get_color(conn, uid.into(), guid.into()).await?;
+    let colors = vec![base, complement_of(base)];
 
-    let guild = ctx.guild_id().unwrap();
-    let conn = ctx.data().pool.acquire().await?;
+    let embed = generated_colors_embed("Complementary colors", &colors);
+    color_buttons(ctx, embed, &colors, ColorSource::Set).await?;
 
-    let new_role = EditRole::new()
-        .name(name.clone())
-        .hoist(false)
-        .mentionable(false)
-        .colour(color);
+    Ok(())
+}
 
-    match color_role {
-        Some(color_role) => {
-            let mut color_id = color_role.role_id;
-            // Color role exists ; edit color
-            update_color_role(conn, color_role.role_id, color).await?;
+/// Colors that go well together with a given color or your own
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn harmony(
+    ctx: Context<'_>,
+    #[description = "The kind of harmony"] scheme: HarmonyScheme,
+    #[description = "Color code - hexadecimal (eg. 0xffaa99 | #11ffaa) leave blank for your own"] color: Option<String>,
+) -> Result<(), Error> {
+    let base = match base_color(ctx, color).await? {
+        Some(base) => base,
+        None => return Ok(()),
+    };
 
-            // Try to edit guild role
-            let res = guild
-                .edit_role(&ctx.http(), color_role.role_id, new_role.clone())
-                .await;
+    let colors: Vec<u32> = scheme.offsets().iter().map(|o| rotate_hue(base, *o)).collect();
 
-            if res.is_err() {
-                // Role no longer seems to exist in the guild? - Try to create it instead then
-                let result = guild.create_role(&ctx.http(), new_role).await?;
+    let embed = generated_colors_embed(&format!("{} colors", scheme.name()), &colors);
+    color_buttons(ctx, embed, &colors, ColorSource::Set).await?;
 
-                // Update new ID in db
-                let conn = ctx.data().pool.acquire().await?;
-                update_color_role_id(conn, color_role.role_id, result.id.get()).await?;
-                color_id = result.id.get();
-            }
+    Ok(())
+}
+
+/// Remove your color role
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn remove(ctx: Context<'_>) -> Result<(), Error> {
+    let uid = ctx.author().id;
+    let guid = ctx.guild_id().unwrap();
 
-            let mem = guild.member(&ctx.http(), uid).await?;
-            mem.add_role(&ctx.http(), color_id).await?;
+    match ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(uid.into(), guid.into()).await? {
+        Some(color_role) => {
+            remove_color_role(ctx.http(), &ctx.data().pool, guid, &color_role, Some(uid), ColorSource::Set).await?;
+            ctx.reply("Your color role has been removed").await?;
+        }
+        None => {
+            ctx.reply("You dont seem to have any color roles made yet").await?;
+        }
+    }
 
-            let reply = CreateReply::default()
-                .content("Color Updated!")
-                .ephemeral(true);
-            ctx.send(reply).await?;
+    Ok(())
+}
+
+/// Remove the color role of another user
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn reset(
+    ctx: Context<'_>,
+    #[description = "The user who's color role you want to remove"] user: User,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    match ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(user.id.into(), guid.into()).await? {
+        Some(color_role) => {
+            remove_color_role(ctx.http(), &ctx.data().pool, guid, &color_role, Some(ctx.author().id), ColorSource::Admin).await?;
+            ctx.reply(format!("Removed the color role of {}", user.mention())).await?;
         }
         None => {
-            // Color role does not exist, create it
-            let result = guild.create_role(&ctx.http(), new_role).await;
+            ctx.reply("That user does not seem to have a color role managed by me").await?;
+        }
+    }
 
-            match result {
-                Ok(role) => {
-                    create_color_role(conn, role.id.into(), uid.into(), guid.into(), color, name)
-                        .await?;
+    Ok(())
+}
 
-                    // Add role incase user doesnt have it yet/anymore
-                    let mem = guild.member(&ctx.http(), uid).await?;
-                    mem.add_role(&ctx.http(), role.id).await?;
+/// Remove the color roles of users that are no longer in this server
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn purge(ctx: Context<'_>) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
 
-                    let reply = CreateReply::default()
-                        .content("Color role created!")
-                        .ephemeral(true);
-                    ctx.send(reply).await?;
-                }
-                Err(err) => {
-                    ctx.reply(format!("Error! : {}", err)).await?;
-                }
+    // Checking every member can take a while on bigger servers
+    ctx.defer_ephemeral().await?;
+
+    let colors = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get_guild(guid.into()).await?;
+
+    let mut removed = 0;
+    for color_role in colors.iter() {
+        match guid.member(ctx.http(), UserId::from(color_role.uid)).await {
+            Ok(_) => continue,
+            Err(err) if is_not_found(&err) => {
+                remove_color_role(ctx.http(), &ctx.data().pool, guid, color_role, Some(ctx.author().id), ColorSource::Admin).await?;
+                removed += 1;
             }
+            Err(err) => return Err(err.into()),
         }
-    };
+    }
+
+    ctx.reply(format!("Purged {} color role(s) of users no longer in this server", removed)).await?;
 
     Ok(())
 }
+
+/// Your recent colors - click one to switch back to it
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn history(ctx: Context<'_>) -> Result<(), Error> {
+    let uid = ctx.author().id;
+    let guid = ctx.guild_id().unwrap();
+
+    let history = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get_history(uid.into(), guid.into(), 15).await?;
+
+    if history.is_empty() {
+        ctx.reply("No color changes recorded for you yet, try `/color set`").await?;
+        return Ok(());
+    }
+
+    let lines: Vec<String> = history.iter()
+        .map(|h| format!("`{}` - {} <t:{}:R>", Colour::new(h.color).hex(), h.source, h.timestamp))
+        .collect();
+
+    // Each color only gets one button, newest first
+    let mut colors: Vec<u32> = vec![];
+    for h in history.iter() {
+        if !colors.contains(&h.color) {
+            colors.push(h.color);
+        }
+    }
+
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let embed = CreateEmbed::default()
+        .title("Color - history")
+        .description(lines.join("\n"))
+        .color(history[0].color)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    color_buttons(ctx, embed, &colors, ColorSource::Set).await?;
+
+    Ok(())
+}
+
+/// Undo your last color change
+#[poise::command(slash_command, ephemeral, guild_only)]
+pub async fn undo(ctx: Context<'_>) -> Result<(), Error> {
+    let uid = ctx.author().id;
+    let guid = ctx.guild_id().unwrap();
+
+    let last = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get_history(uid.into(), guid.into(), 1).await?;
+
+    match last.first() {
+        Some(ColorHistory { id, old_color: Some(old_color), .. }) => {
+            // Undoing is not recorded, the change is dropped from the history instead - unless it could not be undone
+            if insert_or_update_color(ctx, *old_color, None, None, None).await? {
+                ColorRepo::new(&mut ctx.data().pool.acquire().await?).delete_history_entry(*id).await?;
+            }
+        }
+        _ => {
+            ctx.reply("Nothing to undo").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Set the role that color roles get placed directly under - leave blank to disable
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn anchor(
+    ctx: Context<'_>,
+    #[description = "Color roles will be kept right below this role"] role: Option<Role>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?)
+        .set_anchor_role(guid.into(), role.as_ref().map(|r| r.id.into()))
+        .await?;
+
+    match role {
+        Some(role) => {
+            ctx.reply(format!(
+                "Color roles will now be placed under {} - use `/color reposition` to move the existing ones",
+                role.mention()
+            )).await?;
+        }
+        None => {
+            ctx.reply("Color roles will no longer be moved automatically").await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Move all color roles directly under the anchor role
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn reposition(ctx: Context<'_>) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    let mut conn = ctx.data().pool.acquire().await?;
+    let anchor = GuildSettingsRepo::new(&mut conn).get_color_settings(guid.into()).await?.and_then(|s| s.anchor_role);
+
+    let anchor = match anchor {
+        Some(anchor) => RoleId::from(anchor),
+        None => {
+            ctx.reply("No anchor role set, set one first with `/color anchor`").await?;
+            return Ok(());
+        }
+    };
+
+    let managed: Vec<RoleId> = ColorRepo::new(&mut conn)
+        .get_guild(guid.into())
+        .await?
+        .iter()
+        .map(|c| RoleId::from(c.role_id))
+        .collect();
+    drop(conn);
+
+    let moved = reposition_color_roles(ctx.http(), guid, &managed, anchor).await?;
+
+    ctx.reply(format!("Done! moved {} role(s)", moved)).await?;
+
+    Ok(())
+}
+
+/// Delete the color role of members that leave - it gets restored when they come back
+#[poise::command(slash_command, rename = "leave-cleanup", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn leave_cleanup(
+    ctx: Context<'_>,
+    #[description = "Enable / Disable removing the role on leave (True = Enabled / False = Disabled)"] switch: bool,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?).set_remove_on_leave(guid.into(), switch).await?;
+
+    ctx.reply(format!(
+        "Removing color roles of members that leave is now {}",
+        if switch { "Enabled" } else { "Disabled" }
+    )).await?;
+
+    Ok(())
+}
+
+/// Set a channel where every color change gets posted - leave blank to disable
+#[poise::command(slash_command, rename = "log-channel", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn log_channel(
+    ctx: Context<'_>,
+    #[description = "The channel to post color changes in"] channel: Option<Channel>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?)
+        .set_log_channel(guid.into(), channel.as_ref().map(|c| c.id().into()))
+        .await?;
+
+    match channel {
+        Some(channel) => ctx.reply(format!("Color changes will be posted in {}", channel.mention())).await?,
+        None => ctx.reply("Color changes will no longer be posted").await?,
+    };
+
+    Ok(())
+}
+
+/// Set how color roles are named - leave blank for the default {username}
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn naming(
+    ctx: Context<'_>,
+    #[description = "Placeholders: {username} {display_name} {hex} (eg. color-{hex})"] template: Option<String>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    if let Some(template) = &template {
+        if let Err(problem) = validate_template(template) {
+            ctx.reply(problem).await?;
+            return Ok(());
+        }
+    }
+
+    ctx.defer_ephemeral().await?;
+
+    let mut conn = ctx.data().pool.acquire().await?;
+    GuildSettingsRepo::new(&mut conn).set_naming_template(guid.into(), template.clone()).await?;
+
+    // Rename the existing roles to match the new template
+    let colors = ColorRepo::new(&mut conn).get_guild(guid.into()).await?;
+    drop(conn);
+
+    let mut renamed = 0;
+    for color_role in colors.iter() {
+        let mem = match guid.member(ctx.http(), UserId::from(color_role.uid)).await {
+            Ok(mem) => mem,
+            Err(err) if is_not_found(&err) => continue,
+            Err(err) => return Err(err.into()),
+        };
+
+        if sync_role_name(ctx.http(), &ctx.data().pool, guid, &mem.user, mem.nick.as_deref()).await? {
+            renamed += 1;
+        }
+    }
+
+    let template = template.unwrap_or(DEFAULT_TEMPLATE.to_string());
+    ctx.reply(format!(
+        "Color roles are now named like `{}` (eg. `{}`) - renamed {} role(s)",
+        template,
+        render_role_name(&template, &ctx.author().name, ctx.author().global_name.as_deref().unwrap_or(&ctx.author().name), 0xffaa99),
+        renamed
+    )).await?;
+
+    Ok(())
+}
+
+/// Rules for who can have a color role
+#[poise::command(
+    slash_command,
+    subcommands("eligibility_show", "eligibility_role", "eligibility_deny", "eligibility_limits"),
+    guild_only
+)]
+pub async fn eligibility(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Show the rules for who can have a color role
+#[poise::command(slash_command, rename = "show", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn eligibility_show(ctx: Context<'_>) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    let mut conn = ctx.data().pool.acquire().await?;
+    let required: Vec<u64> = ColorRepo::new(&mut conn).required_roles(guid).await?.iter().map(|r| r.get()).collect();
+    let denied: Vec<u64> = ColorRepo::new(&mut conn).denied_users(guid).await?.iter().map(|u| u.get()).collect();
+    let (min_member_age, booster_only) = GuildSettingsRepo::new(&mut conn)
+        .get_color_settings(guid)
+        .await?
+        .map(|s| (s.min_member_age, s.booster_only))
+        .unwrap_or_default();
+    drop(conn);
+
+    let mention_list = |ids: &[u64], prefix: &str| match ids.is_empty() {
+        true => "None".to_string(),
+        false => ids.iter().map(|id| format!("<{}{}>", prefix, id)).collect::<Vec<_>>().join(", "),
+    };
+
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let embed = CreateEmbed::default()
+        .title("Color - eligibility")
+        .field("Required roles (any of)", mention_list(&required, "@&"), false)
+        .field("Denied users", mention_list(&denied, "@"), false)
+        .field("Minimum membership", format!("{} day(s)", min_member_age), true)
+        .field("Boosters only", if booster_only { "Yes" } else { "No" }, true)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+
+    Ok(())
+}
+
+/// Add or remove a role that is required to have a color - members need any one of them
+#[poise::command(slash_command, rename = "require-role", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn eligibility_role(
+    ctx: Context<'_>,
+    #[description = "The role"] role: Role,
+    #[description = "Require the role (True = Add / False = Remove)"] required: bool,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    let changed = ColorRepo::new(&mut ctx.data().pool.acquire().await?)
+        .set_required_role(guid, role.id.into(), required)
+        .await?;
+
+    match (changed, required) {
+        (false, _) => ctx.reply("Nothing changed").await?,
+        (true, true) => ctx.reply(format!("{} is now one of the required roles", role.mention())).await?,
+        (true, false) => ctx.reply(format!("{} is no longer required", role.mention())).await?,
+    };
+
+    Ok(())
+}
+
+/// Add or remove a user from the deny list
+#[poise::command(slash_command, rename = "deny", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn eligibility_deny(
+    ctx: Context<'_>,
+    #[description = "The user"] user: User,
+    #[description = "Deny the user a color (True = Deny / False = Allow again)"] denied: bool,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    let changed = ColorRepo::new(&mut ctx.data().pool.acquire().await?)
+        .set_denied_user(guid, user.id.into(), denied)
+        .await?;
+
+    match (changed, denied) {
+        (false, _) => ctx.reply("Nothing changed").await?,
+        (true, true) => ctx.reply(format!("{} can no longer get a color - use `/color reset` to remove their current one", user.mention())).await?,
+        (true, false) => ctx.reply(format!("{} is allowed to have a color again", user.mention())).await?,
+    };
+
+    Ok(())
+}
+
+/// Set a minimum membership age and / or limit colors to server boosters
+#[poise::command(slash_command, rename = "limits", required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn eligibility_limits(
+    ctx: Context<'_>,
+    #[description = "Days a member has to be on the server first (0 = no minimum)"] min_age_days: Option<u32>,
+    #[description = "Only server boosters can have a color (True = Enabled / False = Disabled)"] boosters_only: Option<bool>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap().into();
+
+    // Keep whatever was not given
+    let mut conn = ctx.data().pool.acquire().await?;
+    let mut repo = GuildSettingsRepo::new(&mut conn);
+    let (current_age, current_boosters) = repo.get_color_settings(guid)
+        .await?
+        .map(|s| (s.min_member_age, s.booster_only))
+        .unwrap_or_default();
+
+    let min_age_days = min_age_days.unwrap_or(current_age);
+    let boosters_only = boosters_only.unwrap_or(current_boosters);
+
+    repo.set_eligibility(guid, min_age_days, boosters_only).await?;
+    drop(conn);
+
+    ctx.reply(format!(
+        "Members need to be here for {} day(s) | Boosters only: {}",
+        min_age_days,
+        if boosters_only { "Enabled" } else { "Disabled" }
+    )).await?;
+
+    Ok(())
+}
+
+/// Compare the color roles with the database - optionally repairing any differences
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn audit(
+    ctx: Context<'_>,
+    #[description = "Repair the differences that were found (Default = False)"] repair: Option<bool>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+    let repair = repair.unwrap_or_default();
+
+    ctx.defer_ephemeral().await?;
+
+    let drift = find_drift(ctx.http(), &ctx.data().pool, guid).await?;
+
+    if drift.is_empty() {
+        ctx.reply("All color roles are in sync with the database").await?;
+        return Ok(());
+    }
+
+    if repair {
+        repair_drift(ctx.http(), &ctx.data().pool, guid, &drift).await?;
+    }
+
+    let lines: Vec<String> = drift.iter().map(|d| format!("- {}", d)).collect();
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let embed = CreateEmbed::default()
+        .title(if repair { "Color audit - repaired" } else { "Color audit" })
+        .description(truncate_lines(&lines, 4000))
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+
+    Ok(())
+}
+
+/// File formats the colors can be exported in
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum ExportFormat {
+    #[name = "JSON"]
+    Json,
+    #[name = "CSV"]
+    Csv,
+}
+
+/// Export all color roles of this server as a file
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn export(
+    ctx: Context<'_>,
+    #[description = "File format (Default = JSON)"] format: Option<ExportFormat>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+    let export = export_colors(&ctx.data().pool, guid).await?;
+
+    let (data, filename) = match format.unwrap_or(ExportFormat::Json) {
+        ExportFormat::Json => (serde_json::to_vec_pretty(&export)?, format!("colors-{}.json", guid)),
+        ExportFormat::Csv => (to_csv(&export).into_bytes(), format!("colors-{}.csv", guid)),
+    };
+
+    let reply = CreateReply::default()
+        .content(format!("Exported {} color role(s)", export.colors.len()))
+        .attachment(CreateAttachment::bytes(data, filename));
+    ctx.send(reply).await?;
+
+    Ok(())
+}
+
+/// Import color roles from an exported file, members that are not on this server are skipped
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn import(
+    ctx: Context<'_>,
+    #[description = "A JSON or CSV file made with /color export"] file: Attachment,
+    #[description = "Only show what would change (Default = False)"]
+    #[rename = "dry-run"] dry_run: Option<bool>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+    let dry_run = dry_run.unwrap_or_default();
+
+    ctx.defer_ephemeral().await?;
+
+    let data = file.download().await?;
+    let colors = match parse_export(&String::from_utf8_lossy(&data)) {
+        Ok(colors) => colors,
+        Err(reason) => {
+            ctx.reply(format!("Could not read `{}` - {}", file.filename, reason)).await?;
+            return Ok(());
+        }
+    };
+
+    let plan = plan_import(ctx.http(), &ctx.data().pool, guid, &colors).await?;
+
+    let planned = plan.iter()
+        .filter(|a| matches!(a, ImportAction::Create { .. } | ImportAction::Update { .. }))
+        .count();
+    let result = match dry_run {
+        true => ImportResult { applied: planned, failed: vec![] },
+        false => apply_import(ctx.http(), &ctx.data().pool, guid, &plan, ctx.author().id).await,
+    };
+
+    let mut lines: Vec<String> = result.failed.iter()
+        .map(|(uid, reason)| format!("- Failed <@{}> - {}", uid, reason))
+        .collect();
+    // The failed ones are listed above already
+    let failed = |uid: &u64| result.failed.iter().any(|(f, _)| f == uid);
+    lines.extend(plan.iter()
+        .filter(|a| match a {
+            ImportAction::Unchanged { .. } => false,
+            ImportAction::Create { uid, .. } | ImportAction::Update { uid, .. } => !failed(uid),
+            _ => true,
+        })
+        .map(|a| format!("- {}", a)));
+
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let embed = CreateEmbed::default()
+        .title(if dry_run { "Color import - dry run" } else { "Color import" })
+        .description(format!(
+            "{} of {} color(s) {}{}\n\n{}",
+            result.applied,
+            plan.len(),
+            if dry_run { "would change" } else { "changed" },
+            if result.failed.is_empty() { String::new() } else { format!(", {} failed", result.failed.len()) },
+            truncate_lines(&lines, 3900)
+        ))
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    ctx.send(CreateReply::default().embed(embed)).await?;
+
+    Ok(())
+}
+
+/// Sends the embed with a button for each of the colors, clicking one of them applies it through the regular set path.
+/// The buttons stay active until none of them got clicked for two minutes.
+async fn color_buttons(ctx: Context<'_>, embed: CreateEmbed, colors: &[u32], source: ColorSource) -> Result<(), Error> {
+    let prefix = format!("{}-color-", ctx.id());
+
+    // Discord allows at most 5 rows of 5 buttons
+    let rows: Vec<CreateActionRow> = colors
+        .chunks(5)
+        .take(5)
+        .map(|chunk| {
+            CreateActionRow::Buttons(chunk.iter()
+                .map(|c| CreateButton::new(format!("{}{:06x}", prefix, c)).label(Colour::new(*c).hex()))
+                .collect())
+        })
+        .collect();
+
+    let handle = ctx.send(CreateReply::default().embed(embed.clone()).components(rows)).await?;
+
+    while let Some(mci) = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(Duration::from_secs(120))
+        .custom_ids(colors.iter().map(|c| format!("{}{:06x}", prefix, c)).collect())
+        .await
+    {
+        mci.create_response(ctx, CreateInteractionResponse::Acknowledge).await?;
+
+        let hex = mci.data.custom_id.trim_start_matches(&prefix);
+        if let Ok(color) = u32::from_str_radix(hex, 16) {
+            insert_or_update_color(ctx, color, Some(source), None, None).await?;
+        }
+    }
+
+    // Timed out - remove the buttons
+    handle.edit(ctx, CreateReply::default().embed(embed).components(vec![])).await?;
+
+    Ok(())
+}
+
+/// The given color code, or the current color of the author when none is given.
+/// Replies with the problem and returns None when there is no usable color.
+async fn base_color(ctx: Context<'_>, color: Option<String>) -> Result<Option<u32>, Error> {
+    match color {
+        Some(color) => match parse_hex(&color) {
+            Ok(color) => Ok(Some(color)),
+            Err(err) => {
+                ctx.reply(format!("Invalid color code? err : {}", err)).await?;
+                Ok(None)
+            }
+        },
+        None => {
+            let color_role = ColorRepo::new(&mut ctx.data().pool.acquire().await?)
+                .get(ctx.author().id.into(), ctx.guild_id().unwrap().into())
+                .await?;
+            match color_role {
+                Some(color_role) => Ok(Some(color_role.color)),
+                None => {
+                    ctx.reply("You dont have a color yet, give a color code to start from").await?;
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+fn generated_colors_embed(title: &str, colors: &[u32]) -> CreateEmbed {
+    let lines: Vec<String> = colors.iter().map(|c| format!("`{}`", Colour::new(*c).hex())).collect();
+    let footer = CreateEmbedFooter::new("Click a color to use it - Generated by CyberBun");
+
+    CreateEmbed::default()
+        .title(title)
+        .description(lines.join("\n"))
+        .color(colors.first().copied().unwrap_or_default())
+        .footer(footer)
+        .timestamp(Timestamp::now())
+}
+
+/// Asks the target of a steal for permission, returns true when they accepted in time
+async fn steal_consent(ctx: Context<'_>, target: &User, color: u32) -> Result<bool, Error> {
+    let accept_id = format!("{}-steal-accept", ctx.id());
+    let decline_id = format!("{}-steal-decline", ctx.id());
+
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(&accept_id).label("Accept").style(ButtonStyle::Success),
+        CreateButton::new(&decline_id).label("Decline").style(ButtonStyle::Danger),
+    ]);
+
+    let content = format!(
+        "{}, {} wants to steal your color `{}` - do you accept?",
+        target.mention(),
+        ctx.author().mention(),
+        Colour::new(color).hex()
+    );
+    let handle = ctx.send(CreateReply::default().content(&content).components(vec![buttons])).await?;
+
+    let answer = ComponentInteractionCollector::new(ctx)
+        .author_id(target.id)
+        .channel_id(ctx.channel_id())
+        .timeout(Duration::from_secs(60))
+        .custom_ids(vec![accept_id.clone(), decline_id])
+        .await;
+
+    let (accepted, outcome) = match answer {
+        Some(mci) => {
+            mci.create_response(ctx, CreateInteractionResponse::Acknowledge).await?;
+
+            if mci.data.custom_id == accept_id {
+                (true, "Accepted")
+            } else {
+                (false, "Declined")
+            }
+        }
+        None => (false, "No answer in time"),
+    };
+
+    handle.edit(ctx, CreateReply::default()
+        .content(format!("{}\n**{}**", content, outcome))
+        .components(vec![])
+    ).await?;
+
+    Ok(accepted)
+}
+
+fn color_info_embed(color: u32, title: String) -> CreateEmbed {
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let colour = Colour::new(color);
+
+    let hsl = to_hsl(color);
+    let hsv = to_hsv(color);
+    let (c, m, y, k) = to_cmyk(color);
+    let (css_name, css_distance) = nearest_css_name(color);
+    let dark = contrast_ratio(color, DISCORD_DARK);
+    let light = contrast_ratio(color, DISCORD_LIGHT);
+
+    let embed = CreateEmbed::default()
+        .title(title)
+        .color(colour)
+        .field("Hex code:", colour.hex(), false)
+        .field("R", colour.r().to_string(), true)
+        .field("G", colour.g().to_string(), true)
+        .field("B", colour.b().to_string(), true)
+        .field("HSL", format!("{:.0}°, {:.0}%, {:.0}%", hsl.h, hsl.s * 100.0, hsl.l * 100.0), true)
+        .field("HSV", format!("{:.0}°, {:.0}%, {:.0}%", hsv.h, hsv.s * 100.0, hsv.v * 100.0), true)
+        .field("CMYK", format!("{:.0}%, {:.0}%, {:.0}%, {:.0}%", c * 100.0, m * 100.0, y * 100.0, k * 100.0), true)
+        .field(
+            "Nearest CSS color",
+            if css_distance < 1.0 { css_name.to_string() } else { format!("{} (ΔE {:.1})", css_name, css_distance) },
+            false,
+        )
+        .field("Contrast - dark theme", format!("{:.2}:1 {}", dark, contrast_rating(dark)), true)
+        .field("Contrast - light theme", format!("{:.2}:1 {}", light, contrast_rating(light)), true)
+        .footer(footer)
+        .timestamp(Timestamp::now());
+
+    embed
+}
+
+async fn help_embed(user: &Member, color_role_id: DbRoleId, http: &Http, bot_avatar: String) -> Result<CreateEmbed, Error> {
+    let role_ids = &user.roles;
+    let guild_roles = user.guild_id.roles(http).await?;
+    let color_id = RoleId::from(color_role_id);
+
+    let u_roles: HashMap<&RoleId, &Role>= guild_roles.iter().filter(|r|
+        role_ids.contains(r.0)
+    ).collect();
+    let color_role_pos = u_roles.get(&color_id).unwrap().position;
+    let no_color = Colour::from(0);
+
+                    // - Title / content / false
+    let mut higher: Vec<(String, String, bool)> = vec![];
+
+    for (_, r) in u_roles.iter() {
+        if r.position.gt(&color_role_pos) && r.colour.0.ne(&no_color.0) {
+            higher.push( (format!("Role: {}", r.name), format!("Color: {}", r.colour.hex()), false) );
+        }
+    }
+
+    let footer = CreateEmbedFooter::new("Generated by CyberBun");
+    let embed = CreateEmbed::default()
+    .title("Color - help")
+    .description("
+    Is your custom color not displaying correctly?
+    The following roles if any can be overwriting the color role.
+    Simply set the color for these roles to \"Default\" to fix this issue.
+    ")
+    .thumbnail(bot_avatar)
+    .timestamp(Timestamp::now())
+    .footer(footer)
+    .fields(higher);
+
+    Ok(embed)
+}
+
+/// Creates or updates the color role of the author, returns true when the color got applied.
+/// The change is recorded in the color history under the given source, None leaves the history untouched.
+/// With `revert_at` the color is temporary and switches back to the current one then - or to the color a pending revert
+/// already goes back to, so stacked temporary colors still end on the permanent color.
+/// Without it any temporary color that was pending a revert is replaced by this one.
+/// A stolen color names the member it was taken from in `victim`, for the log channel.
+async fn insert_or_update_color(
+    ctx: Context<'_>,
+    color: u32,
+    source: Option<ColorSource>,
+    revert_at: Option<u64>,
+    victim: Option<UserId>,
+) -> Result<bool, Error> {
+    match apply_color(ctx, color, source, revert_at, victim).await {
+        Ok(applied) => Ok(applied),
+        // Discord refusing the change is something the user or an admin can fix, tell them how
+        Err(err) => match describe_error(&err) {
+            Some(message) => {
+                ctx.reply(message).await?;
+                Ok(false)
+            }
+            None => Err(err),
+        },
+    }
+}
+
+async fn apply_color(
+    ctx: Context<'_>,
+    color: u32,
+    source: Option<ColorSource>,
+    revert_at: Option<u64>,
+    victim: Option<UserId>,
+) -> Result<bool, Error> {
+    let uid = ctx.author().id;
+
+    let guid = match ctx.guild_id() {
+        Some(g) => g,
+        None => {
+            ctx.reply("Can only be used inside a server").await?;
+            return Ok(false);
+        }
+    };
+
+    // see if color exists
+    let color_role = ColorRepo::new(&mut ctx.data().pool.acquire().await?).get(uid.into(), guid.into()).await?;
+
+    let guild = ctx.guild_id().unwrap();
+    let mem = guild.member(&ctx.http(), uid).await?;
+
+    if let Some(reason) = check_eligibility(&ctx.data().pool, &mem).await? {
+        ctx.reply(reason.to_string()).await?;
+        return Ok(false);
+    }
+
+    let role_id = color_role.as_ref().map(|c| RoleId::from(c.role_id));
+    if let Some(problem) = preflight(ctx.http(), guild, role_id).await? {
+        ctx.reply(problem.to_string()).await?;
+        return Ok(false);
+    }
+
+    let name = role_name_for(&ctx.data().pool, guild, &mem.user, mem.nick.as_deref(), color).await?;
+
+    // Marked first, so the role update event does not take this for a change made in the server settings.
+    // Cleared again when the change fails, see `undo_color_role`.
+    if let Some(color_role) = color_role.as_ref() {
+        ColorRepo::new(&mut ctx.data().pool.acquire().await?).set_pending_color(color_role.role_id, Some(color)).await?;
+    }
+
+    // Discord goes first and the database only stores what discord accepted, so no write lock is held while waiting on it
+    let mut created = None;
+    let role_id = match edit_color_role(ctx, &mem, color_role.as_ref(), &name, color, &mut created).await {
+        Ok(role_id) => role_id,
+        Err(err) => {
+            undo_color_role(ctx, guild, color_role.as_ref(), created).await;
+            return Err(err);
+        }
+    };
+
+    let mut tx = ctx.data().pool.begin().await?;
+    let saved: Result<(), Error> = async {
+        let mut repo = ColorRepo::new(&mut tx);
+        match color_role.as_ref() {
+            Some(color_role) => {
+                if DbRoleId::from(role_id) != color_role.role_id {
+                    repo.update_role_id(color_role.role_id, role_id.into()).await?;
+                }
+                repo.update_color(role_id.into(), color).await?;
+                if name != color_role.role_name {
+                    repo.update_name(role_id.into(), name.clone()).await?;
+                }
+            }
+            None => repo.create(role_id.into(), uid.into(), guid.into(), color, name.clone()).await?,
+        }
+
+        if let Some(source) = source {
+            repo.add_history(uid.into(), guid.into(), color_role.as_ref().map(|c| c.color), color, source).await?;
+        }
+
+        let mut jobs = ColorJobRepo::new(&mut tx);
+        match (revert_at, color_role.as_ref()) {
+            (Some(revert_at), Some(color_role)) => jobs.schedule_revert(uid.into(), guid.into(), color_role.color, revert_at).await?,
+            _ => jobs.cancel_revert(uid.into(), guid.into()).await?,
+        }
+
+        // A color picked by hand ends any cycle
+        ColorCycleRepo::new(&mut tx).stop(uid.into(), guid.into()).await?;
+
+        Ok(())
+    }.await;
+
+    let saved = match saved {
+        Ok(()) => tx.commit().await,
+        Err(err) => tx.rollback().await.and(Err(err)),
+    };
+
+    if let Err(err) = saved {
+        undo_color_role(ctx, guild, color_role.as_ref(), created).await;
+        return Err(err);
+    }
+
+    let change = ColorChange {
+        uid,
+        actor: Some(uid),
+        victim,
+        old_color: color_role.as_ref().map(|c| c.color),
+        color: Some(color),
+        source: source.unwrap_or(ColorSource::Set),
+    };
+    log_color_change(ctx.http(), &ctx.data().pool, guild, change).await?;
+
+    let reply = CreateReply::default()
+        .content(if color_role.is_some() { "Color Updated!" } else { "Color role created!" })
+        .ephemeral(true);
+    ctx.send(reply).await?;
+
+    Ok(true)
+}
+
+/// Makes the discord side of a color change and returns the color role, nothing is written to the database here.
+/// Any role that had to be created is put in `created`, so it can be cleaned up when the change fails later on.
+async fn edit_color_role(
+    ctx: Context<'_>,
+    mem: &Member,
+    color_role: Option<&ColorRow>,
+    name: &str,
+    color: u32,
+    created: &mut Option<RoleId>,
+) -> Result<RoleId, Error> {
+    let guild = mem.guild_id;
+
+    let new_role = EditRole::new()
+        .name(name)
+        .hoist(false)
+        .mentionable(false)
+        .colour(color);
+
+    let role_id = match color_role {
+        Some(color_role) => {
+            // Color role exists ; edit color
+            match guild.edit_role(ctx.http(), color_role.role_id, new_role.clone()).await {
+                Ok(role) => role.id,
+                Err(err) if is_not_found(&err) => {
+                    // Role no longer seems to exist in the guild? - Try to create it instead then
+                    let role = guild.create_role(ctx.http(), new_role).await?;
+                    *created = Some(role.id);
+                    role.id
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+        None => {
+            // Color role does not exist, create it
+            let role = guild.create_role(ctx.http(), new_role).await?;
+            *created = Some(role.id);
+            role.id
+        }
+    };
+
+    position_color_role(ctx.http(), &ctx.data().pool, guild, role_id).await?;
+
+    // Add role incase user doesnt have it yet/anymore
+    mem.add_role(ctx.http(), role_id).await?;
+
+    Ok(role_id)
+}
+
+/// Puts the roles back the way they were after a color change failed part way - a role made along the way is deleted,
+/// an edited one gets its old color and name back. Failing at that is only logged, the original error is what the user sees.
+async fn undo_color_role(ctx: Context<'_>, guild: GuildId, color_role: Option<&ColorRow>, created: Option<RoleId>) {
+    // Left in place, the next change to that color made in the server settings would be taken for the bot's own
+    if let Some(color_role) = color_role {
+        let cleared = match ctx.data().pool.acquire().await {
+            Ok(mut conn) => ColorRepo::new(&mut conn).clear_pending_color(color_role.uid, guild.into()).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = cleared {
+            warn!("Could not clear the pending color of role {} - {}", color_role.role_id, err);
+        }
+    }
+
+    match (created, color_role) {
+        (Some(role_id), _) => match guild.delete_role(ctx.http(), role_id).await {
+            Ok(_) => info!("Deleted color role {} after a failed color change", role_id),
+            Err(err) => warn!("Could not delete color role {} after a failed color change - {}", role_id, err),
+        },
+        (None, Some(color_role)) => {
+            let old_role = EditRole::new().name(&color_role.role_name).colour(color_role.color);
+            match guild.edit_role(ctx.http(), color_role.role_id, old_role).await {
+                Ok(_) => info!("Restored color role {} after a failed color change", color_role.role_id),
+                Err(err) => warn!("Could not restore color role {} after a failed color change - {}", color_role.role_id, err),
+            }
+        }
+        (None, None) => {}
+    }
+}
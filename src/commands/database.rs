@@ -0,0 +1,61 @@
+use crate::db_handlers::maintenance::{backup_now, integrity_check, prune_backups, vacuum as vacuum_db};
+
+use super::{Context, Error};
+
+#[poise::command(slash_command, owners_only, hide_in_help, subcommands("backup", "vacuum", "integrity"))]
+pub async fn db(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Write a backup of the database right away, old backups are pruned like the scheduled ones
+#[poise::command(slash_command, owners_only, hide_in_help, ephemeral)]
+pub async fn backup(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let settings = &ctx.data().config.backups;
+    let path = backup_now(&ctx.data().pool, settings).await?;
+    let removed = prune_backups(settings).await?;
+
+    ctx.reply(format!(
+        "Backup written to `{}` | Removed {} old backup(s), keeping {}",
+        path.display(),
+        removed,
+        settings.keep
+    )).await?;
+
+    Ok(())
+}
+
+/// Rebuild the database file to give back unused space
+#[poise::command(slash_command, owners_only, hide_in_help, ephemeral)]
+pub async fn vacuum(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let (before, after) = vacuum_db(&mut ctx.data().pool.acquire().await?).await?;
+
+    ctx.reply(format!("Database vacuumed - {} KiB -> {} KiB", before / 1024, after / 1024)).await?;
+
+    Ok(())
+}
+
+/// Check the database file for corruption
+#[poise::command(slash_command, owners_only, hide_in_help, ephemeral)]
+pub async fn integrity(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let problems = integrity_check(&mut ctx.data().pool.acquire().await?).await?;
+
+    match problems.is_empty() {
+        true => { ctx.reply("Integrity check passed, no problems found").await?; }
+        false => {
+            let lines: Vec<String> = problems.iter().take(20).map(|p| format!("- {}", p)).collect();
+            ctx.reply(format!(
+                "Integrity check found {} problem(s) - restore a backup with `cyber_bun2 restore <file>`\n{}",
+                problems.len(),
+                lines.join("\n")
+            )).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,13 @@
 use super::{Context, Error};
-use poise::serenity_prelude::CreateEmbed;
+use poise::serenity_prelude::{CreateEmbed, CreateEmbedFooter, Timestamp};
 use poise::CreateReply;
 use tracing::info;
 
 pub mod colors;
+pub mod database;
+pub mod privacy;
 pub mod reminders;
+pub mod settings;
 pub mod starboard;
 
 /// Show the HELP menu
@@ -15,7 +18,7 @@ pub async fn help(
 ) -> Result<(), Error> {
     let config = poise::builtins::HelpConfiguration {
         extra_text_at_bottom: "\
-            Type ~help command for more info on a command.
+            Type help followed by a command for more info on it, with this server's prefix or by mentioning me.
             You can edit your message to the bot and the bot will edit its response.",
         ..Default::default()
     };
@@ -36,9 +39,20 @@ pub async fn register_commands(ctx: Context<'_>) -> Result<(), Error> {
 pub async fn sysinfo(ctx: Context<'_>) -> Result<(), Error> {
     // Todo: Find a good crate to get current process info - cpu usage / ram usage / etc.
 
-    let embed = CreateEmbed::new()
-        .title("System Resource Load");
+    let pool = ctx.data().pool.stats();
 
+    let embed = CreateEmbed::new()
+        .title("System Resource Load")
+        .field("Database", format!("{:?}", pool.backend), true)
+        .field("Connections", format!("{} in use | {} idle | {}/{} open", pool.in_use, pool.idle, pool.size, pool.max_connections), true)
+        .field("Connection waits", format!(
+            "{} acquired | {:.2?} average | {:.2?} longest",
+            pool.acquires,
+            pool.average_wait,
+            pool.longest_wait
+        ), false)
+        .footer(CreateEmbedFooter::new("Generated by CyberBun"))
+        .timestamp(Timestamp::now());
 
     ctx.send(CreateReply::default().embed(embed)).await?;
 
@@ -0,0 +1,100 @@
+use crate::color_roles::remove_color_role;
+use crate::db_handlers::color_repo::{ColorRepo, ColorSource};
+use crate::db_handlers::privacy_repo::PrivacyRepo;
+
+use super::{Context, Error};
+use poise::serenity_prelude::{
+    ButtonStyle, ComponentInteractionCollector, CreateActionRow, CreateAttachment, CreateButton, CreateInteractionResponse, CreateMessage, GuildId,
+};
+use poise::CreateReply;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+#[poise::command(slash_command, subcommands("export", "delete"))]
+pub async fn privacy(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Get a copy of everything CyberBun stores about you, sent to your DMs
+#[poise::command(slash_command, ephemeral)]
+pub async fn export(ctx: Context<'_>) -> Result<(), Error> {
+    ctx.defer_ephemeral().await?;
+
+    let uid = ctx.author().id;
+    let data = PrivacyRepo::new(&mut ctx.data().pool.acquire().await?).export_user(uid.into()).await?;
+
+    let file = CreateAttachment::bytes(serde_json::to_vec_pretty(&data)?, format!("cyberbun-{}.json", uid));
+    let message = CreateMessage::new()
+        .content("Here is everything CyberBun stores about you - use `/privacy delete` to erase it")
+        .add_file(file);
+
+    match ctx.author().direct_message(ctx, message).await {
+        Ok(_) => { ctx.reply("Sent you a DM with your data").await?; }
+        Err(_) => { ctx.reply("Could not DM you - allow direct messages from server members and try again").await?; }
+    }
+
+    Ok(())
+}
+
+/// Erase everything CyberBun stores about you, your color roles get deleted as well
+#[poise::command(slash_command, ephemeral)]
+pub async fn delete(ctx: Context<'_>) -> Result<(), Error> {
+    let confirm_id = format!("{}-privacy-confirm", ctx.id());
+    let cancel_id = format!("{}-privacy-cancel", ctx.id());
+
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(&confirm_id).label("Delete my data").style(ButtonStyle::Danger),
+        CreateButton::new(&cancel_id).label("Cancel").style(ButtonStyle::Secondary),
+    ]);
+
+    let content = "This removes your color roles in every server, your color history and preferences and all your reminders. It can't be undone.";
+    let handle = ctx.send(CreateReply::default().content(content).components(vec![buttons])).await?;
+
+    let answer = ComponentInteractionCollector::new(ctx)
+        .author_id(ctx.author().id)
+        .channel_id(ctx.channel_id())
+        .timeout(Duration::from_secs(60))
+        .custom_ids(vec![confirm_id.clone(), cancel_id])
+        .await;
+
+    let confirmed = match answer {
+        Some(mci) => {
+            mci.create_response(ctx, CreateInteractionResponse::Acknowledge).await?;
+            mci.data.custom_id == confirm_id
+        }
+        None => false,
+    };
+
+    if !confirmed {
+        handle.edit(ctx, CreateReply::default().content("Nothing was deleted").components(vec![])).await?;
+        return Ok(());
+    }
+
+    let uid = ctx.author().id;
+    let pool = &ctx.data().pool;
+
+    // Roles in servers the bot can't reach anymore stay behind, their rows are deleted below either way
+    let color_roles = ColorRepo::new(&mut pool.acquire().await?).get_user(uid.into()).await?;
+    let mut kept_roles = 0;
+    for color_role in color_roles.iter() {
+        if let Err(err) = remove_color_role(ctx.http(), pool, GuildId::from(color_role.guid), color_role, Some(uid), ColorSource::Set).await {
+            warn!("Could not remove color role {} for a data deletion - {}", color_role.role_id, err);
+            kept_roles += 1;
+        }
+    }
+
+    let mut tx = pool.begin().await?;
+    let deleted = PrivacyRepo::new(&mut tx).delete_user(uid.into()).await?;
+    tx.commit().await?;
+
+    info!("Deleted the data of user {} - {} row(s)", uid, deleted);
+
+    let mut outcome = format!("Your data has been deleted - {} color role(s) removed", color_roles.len() - kept_roles);
+    if kept_roles != 0 {
+        outcome.push_str(&format!(", {} could not be removed from their server and have to be deleted by its admins", kept_roles));
+    }
+
+    handle.edit(ctx, CreateReply::default().content(outcome).components(vec![])).await?;
+
+    Ok(())
+}
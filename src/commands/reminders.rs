@@ -1,6 +1,6 @@
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use crate::db_handlers::reminder_handlers::{new_reminder, NewReminder};
+use crate::db_handlers::reminder_repo::{NewReminder, ReminderRepo};
 
 use super::super::{Data, Error};
 use poise::Modal;
@@ -25,10 +25,7 @@ pub async fn remindme(ctx: ApplicationContext<'_>) -> Result<(), Error> {
     let hours = hours.parse::<u32>();
     let minutes = minutes.parse::<u32>();
 
-    if days.is_ok() && hours.is_ok() && minutes.is_ok() {
-        let days = days.unwrap();
-        let hours = hours.unwrap();
-        let minutes = minutes.unwrap();
+    if let (Ok(days), Ok(hours), Ok(minutes)) = (days, hours, minutes) {
         // All where valid numbers for u32
         ctx.reply(format!(
             "Setting reminder for you in {} days {} hours {} and minutes",
@@ -49,13 +46,13 @@ pub async fn remindme(ctx: ApplicationContext<'_>) -> Result<(), Error> {
         let new = NewReminder { 
             timestamp: timestamp.as_secs(), 
             message: modal_vals.message, 
-            user_id: ctx.author().id.get(),
-            channel_id: ctx.channel_id().get(), 
+            user_id: ctx.author().id.into(),
+            channel_id: ctx.channel_id().into(), 
+            guild_id: ctx.guild_id().map(Into::into),
             private: false, 
         };
 
-        let conn = ctx.data.pool.acquire().await?;
-        new_reminder(conn, new).await?;
+        ReminderRepo::new(&mut ctx.data.pool.acquire().await?).create(new).await?;
     } else {
         // Invalid number detected - tell user
         ctx.reply("One of the inputs you gave was not a valid number, please only use numbers in the fields").await?;
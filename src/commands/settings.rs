@@ -0,0 +1,32 @@
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::prefix::{is_valid_prefix, MAX_PREFIX_LENGTH};
+
+use super::{Context, Error};
+
+#[poise::command(slash_command, subcommands("prefix"))]
+pub async fn settings(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Change the prefix of the text commands in this server - leave it empty to go back to the default
+#[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only, ephemeral)]
+pub async fn prefix(
+    ctx: Context<'_>,
+    #[description = "The new prefix, without spaces (Default = the bot's prefix)"] prefix: Option<String>,
+) -> Result<(), Error> {
+    let guid = ctx.guild_id().unwrap();
+
+    if let Some(prefix) = prefix.as_deref().filter(|p| !is_valid_prefix(p)) {
+        ctx.reply(format!("`{}` can't be used as a prefix - it needs 1 to {} characters, no spaces and no mentions", prefix, MAX_PREFIX_LENGTH)).await?;
+        return Ok(());
+    }
+
+    let default_stars = ctx.data().config.default_stars;
+    GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?).set_prefix(guid.into(), prefix.clone(), default_stars).await?;
+    ctx.data().prefixes.set(guid, prefix.clone());
+
+    let prefix = prefix.unwrap_or(ctx.data().config.prefix.clone());
+    ctx.reply(format!("Text commands now use `{}` in this server - mentioning me works as well", prefix)).await?;
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::db_handlers::starboard_handlers::*;
+use crate::db_handlers::guild_settings_repo::{GuildSettings, GuildSettingsRepo};
 
 use super::{Context, Error};
 use poise::serenity_prelude::{Channel, Mentionable};
@@ -15,22 +15,26 @@ pub async fn setup(
     ctx: Context<'_>,
     #[description = "The channel to post the starboard messages in"] channel: Channel,
     #[description = "Enable / Disable the starboard (True = Enabled / False = Disabled)"] enabled: Option<bool>,
-    #[description = "Minimum star reactions to pin (Default = 3)"]#[min = 1]  min_stars: Option<u8>,
+    #[description = "Minimum star reactions to pin (Default = 3, unless the bot is configured otherwise)"]#[min = 1]  min_stars: Option<u8>,
 ) -> Result<(), Error> {
     // See if guild already has settings in DB and update or insert new settings
     // Set the channel to use for the starboard
 
-    let enabled = match enabled { Some(opt) => opt, None => false };
-    let min_stars = match min_stars { Some(opt) => opt, None => 3 };
+    let enabled = enabled.unwrap_or_default();
+    let min_stars = min_stars.unwrap_or(ctx.data().config.default_stars);
 
     // Try to get existing settings
-    let conn = ctx.data().pool.acquire().await?;
-    let res = get_guild_settings(conn, ctx.guild_id().unwrap().get()).await?;
+    let mut conn = ctx.data().pool.acquire().await?;
+    let mut repo = GuildSettingsRepo::new(&mut conn);
+    let res = repo.get(ctx.guild_id().unwrap().into()).await?;
 
     let new_settings: GuildSettings = match res {
         Some(mut settings) => {
-            // Existing settings found
-            settings.starboard_channel = Some(channel.id().get());
+            // Existing settings found - a guild that only set a prefix has no starboard yet
+            if settings.starboard_channel.is_none() {
+                settings.starboard_enabled = enabled;
+            }
+            settings.starboard_channel = Some(channel.id().into());
             settings.starboard_min = min_stars;
 
             settings
@@ -38,16 +42,16 @@ pub async fn setup(
         None => {
             // Settings dont exist in DB
             GuildSettings {
-                guild_id: ctx.guild_id().unwrap().get(),
+                guild_id: ctx.guild_id().unwrap().into(),
                 starboard_enabled: enabled,
-                starboard_channel: Some(channel.id().get()),
+                starboard_channel: Some(channel.id().into()),
                 starboard_min: min_stars,
             }
         },
     };
     
-    let conn = ctx.data().pool.acquire().await?;
-    update_guild_settings(conn, new_settings).await?;
+    repo.update(new_settings).await?;
+    drop(conn);
 
     ctx.reply(
         format!("New settings - channel set to {} | Starboard is currently: {}",
@@ -71,18 +75,20 @@ pub async fn enabled(
     // - When trying to enable - check if there is a channel set already!
     
     // Try to get existing settings
-    let conn = ctx.data().pool.acquire().await?;
-    let res = get_guild_settings(conn, ctx.guild_id().unwrap().get()).await?;
+    let mut conn = ctx.data().pool.acquire().await?;
+    let mut repo = GuildSettingsRepo::new(&mut conn);
+    let res = repo.get(ctx.guild_id().unwrap().into()).await?;
 
-    match res {
+    // Setting a prefix alone leaves a row without a channel behind
+    match res.and_then(|settings| settings.starboard_channel) {
         Some(_) => {
-            let conn = ctx.data().pool.acquire().await?;
-            toggle_starboard(conn, ctx.guild_id().unwrap().get(), switch).await?;
+            repo.toggle_starboard(ctx.guild_id().unwrap().into(), switch).await?;
+            drop(conn);
 
             ctx.reply(format!("Starboard is now {}", if switch { "Enabled" } else { "Disabled" } )).await?;
         },
         None => {
-            ctx.reply(format!("Please configure a channel to use first with `/starboard setup`")).await?;
+            ctx.reply("Please configure a channel to use first with `/starboard setup`").await?;
         },
     };
 
@@ -94,17 +100,16 @@ pub async fn enabled(
 #[poise::command(slash_command, required_permissions = "ADMINISTRATOR", guild_only)]
 pub async fn stars(
     ctx: Context<'_>,
-    #[description = "Minimum star reactions to pin (Default = 3)"]#[min = 1] min_stars: u8,
+    #[description = "Minimum star reactions to pin (Default = 3, unless the bot is configured otherwise)"]#[min = 1] min_stars: u8,
 ) -> Result<(), Error> {
 
-    let guid = ctx.guild_id().unwrap().get();
+    let guid = ctx.guild_id().unwrap().into();
 
-    let conn = ctx.data().pool.acquire().await?;
-    let res = set_required_stars(conn, guid, min_stars).await?;
+    let res = GuildSettingsRepo::new(&mut ctx.data().pool.acquire().await?).set_required_stars(guid, min_stars).await?;
 
     match res {
         true => { ctx.reply(format!("Setting edited, messages now require at least {} stars", min_stars)).await?; }
-        false => { ctx.reply("No existing settings found, did you run the setup command atleast once?").await?; }
+        false => { ctx.reply("No starboard channel found, did you run the setup command atleast once?").await?; }
     }
 
     Ok(())
@@ -0,0 +1,333 @@
+//! The settings of the bot, read from a TOML file - `cyberbun.toml`, or the file `CYBERBUN_CONFIG` points at.
+//! Every setting can be overridden with a `CYBERBUN_*` environment variable, so setups that only use the environment keep working.
+//! All problems with the settings are collected and reported together before the bot starts.
+
+use std::env;
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use poise::serenity_prelude::utils::validate_token;
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::db_handlers::backend::{Backend, DbSettings};
+use crate::db_handlers::maintenance::{BackupSettings, RetentionSettings};
+use crate::prefix::{is_valid_prefix, MAX_PREFIX_LENGTH};
+
+const DEFAULT_PATH: &str = "cyberbun.toml";
+
+pub struct Config {
+    pub token: String,
+    /// What gets logged, in the syntax of `RUST_LOG`
+    pub filter: String,
+    /// Prefix of the text commands
+    pub prefix: String,
+    /// Gif posted with every stolen color, a relative path starts in the working directory and not next to the config file
+    pub steal_gif: PathBuf,
+    /// Time between checks for reminders that are due
+    pub reminder_interval: Duration,
+    /// Stars a message needs to make it onto the starboard, when the guild didn't pick a number
+    pub default_stars: u8,
+    pub database_url: String,
+    pub database: DbSettings,
+    pub backups: BackupSettings,
+    pub retention: RetentionSettings,
+}
+
+/// Everything that is wrong with the settings
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<String>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The configuration has {} problem(s):", self.0.len())?;
+        for problem in self.0.iter() {
+            write!(f, "\n- {}", problem)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// The config file as it is written, anything left out falls back to the environment and then the defaults
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    token: Option<String>,
+    filter: Option<String>,
+    prefix: Option<String>,
+    steal_gif: Option<PathBuf>,
+    reminder_interval_secs: Option<u64>,
+    default_stars: Option<u8>,
+    database: DatabaseFile,
+    backups: BackupsFile,
+    retention: RetentionFile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseFile {
+    url: Option<String>,
+    max_connections: Option<u32>,
+    min_connections: Option<u32>,
+    acquire_timeout_secs: Option<u64>,
+    busy_timeout_ms: Option<u64>,
+    journal_mode: Option<String>,
+    synchronous: Option<String>,
+    foreign_keys: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct BackupsFile {
+    dir: Option<PathBuf>,
+    /// 0 turns the automatic backups off
+    hours: Option<u64>,
+    keep: Option<usize>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RetentionFile {
+    /// 0 keeps everything
+    days: Option<u64>,
+    guild_grace_days: Option<u64>,
+}
+
+/// Picks every setting from the environment or the file and remembers what was wrong along the way
+struct Loader<'a> {
+    env: &'a dyn Fn(&str) -> Option<String>,
+    problems: Vec<String>,
+}
+
+impl Loader<'_> {
+    /// The environment variable wins over the file, a variable that doesn't parse is a problem
+    fn get<T: FromStr>(&mut self, var: &str, file: Option<T>) -> Option<T> {
+        match (self.env)(var) {
+            Some(value) => match value.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    self.problems.push(format!("{} - {:?} is not a valid value", var, value));
+                    None
+                }
+            },
+            None => file,
+        }
+    }
+
+    fn or<T: FromStr>(&mut self, var: &str, file: Option<T>, default: T) -> T {
+        self.get(var, file).unwrap_or(default)
+    }
+
+    /// Like `get`, for settings the file holds as text
+    fn parse<T: FromStr>(&mut self, key: &str, var: &str, file: Option<String>) -> Option<T> {
+        match (self.env)(var) {
+            Some(_) => self.get(var, None),
+            None => file.and_then(|value| match value.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    self.problems.push(format!("{} - {:?} is not a valid value", key, value));
+                    None
+                }
+            }),
+        }
+    }
+
+    fn required(&mut self, key: &str, var: &str, file: Option<String>) -> String {
+        let reported = self.problems.len();
+        match self.get(var, file) {
+            Some(value) if !value.is_empty() => value,
+            // A value that doesn't parse is already reported, it isn't missing as well
+            None if self.problems.len() > reported => String::new(),
+            _ => {
+                self.problems.push(format!("{} is missing - set it in the config file or with {}", key, var));
+                String::new()
+            }
+        }
+    }
+
+    fn check(&mut self, ok: bool, problem: impl FnOnce() -> String) {
+        if !ok {
+            self.problems.push(problem());
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file, when there is one, with the environment on top
+    pub fn load() -> Result<Self, ConfigErrors> {
+        let (path, explicit) = match env::var("CYBERBUN_CONFIG") {
+            Ok(path) => (PathBuf::from(path), true),
+            Err(_) => (PathBuf::from(DEFAULT_PATH), false),
+        };
+
+        // Only a file that was asked for has to exist
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => Some(text),
+            Err(err) if err.kind() == ErrorKind::NotFound && !explicit => None,
+            Err(err) => return Err(ConfigErrors(vec![format!("Could not read {} - {}", path.display(), err)])),
+        };
+
+        Config::from_sources(text.as_deref(), &|var| env::var(var).ok())
+    }
+
+    fn from_sources(text: Option<&str>, env: &dyn Fn(&str) -> Option<String>) -> Result<Self, ConfigErrors> {
+        let file: ConfigFile = match text {
+            Some(text) => toml::from_str(text).map_err(|err| ConfigErrors(vec![format!("The config file is not valid - {}", err)]))?,
+            None => ConfigFile::default(),
+        };
+
+        let mut load = Loader { env, problems: vec![] };
+
+        let token = load.required("token", "CYBERBUN_TOKEN", file.token);
+        load.check(token.is_empty() || validate_token(&token).is_ok(), || "token - is not a valid bot token".to_string());
+
+        let filter = load.or("CYBERBUN_FILTER", file.filter, "info".to_string());
+        load.check(EnvFilter::try_new(&filter).is_ok(), || format!("filter - {:?} is not a valid log filter", filter));
+
+        let prefix = load.or("CYBERBUN_PREFIX", file.prefix, "~".to_string());
+        load.check(is_valid_prefix(&prefix), || format!("prefix - needs 1 to {} characters, no spaces and no mentions", MAX_PREFIX_LENGTH));
+
+        let steal_gif = load.or("CYBERBUN_STEAL_GIF", file.steal_gif, PathBuf::from("attachments/steal.gif"));
+        load.check(steal_gif.is_file(), || format!("steal_gif - {} does not exist (relative to the working directory)", steal_gif.display()));
+
+        let reminder_secs = load.or("CYBERBUN_REMINDER_INTERVAL_SECS", file.reminder_interval_secs, 30);
+        load.check(reminder_secs > 0, || "reminder_interval_secs - has to be at least 1".to_string());
+
+        let default_stars = load.or("CYBERBUN_DEFAULT_STARS", file.default_stars, 3);
+        load.check(default_stars > 0, || "default_stars - has to be at least 1".to_string());
+
+        let database_url = load.required("database.url", "CYBERBUN_DB_URL", file.database.url);
+        if let (false, Err(err)) = (database_url.is_empty(), Backend::from_url(&database_url)) {
+            load.problems.push(format!("database.url - {}", err));
+        }
+
+        let db = DbSettings::default();
+        let database = DbSettings {
+            max_connections: load.or("CYBERBUN_DB_MAX_CONNECTIONS", file.database.max_connections, db.max_connections),
+            min_connections: load.or("CYBERBUN_DB_MIN_CONNECTIONS", file.database.min_connections, db.min_connections),
+            acquire_timeout: Duration::from_secs(load.or(
+                "CYBERBUN_DB_ACQUIRE_TIMEOUT_SECS",
+                file.database.acquire_timeout_secs,
+                db.acquire_timeout.as_secs(),
+            )),
+            busy_timeout: Duration::from_millis(load.or(
+                "CYBERBUN_DB_BUSY_TIMEOUT_MS",
+                file.database.busy_timeout_ms,
+                db.busy_timeout.as_millis() as u64,
+            )),
+            journal_mode: load.parse("database.journal_mode", "CYBERBUN_DB_JOURNAL_MODE", file.database.journal_mode).unwrap_or(db.journal_mode),
+            synchronous: load.parse("database.synchronous", "CYBERBUN_DB_SYNCHRONOUS", file.database.synchronous).unwrap_or(db.synchronous),
+            foreign_keys: load.or("CYBERBUN_DB_FOREIGN_KEYS", file.database.foreign_keys, db.foreign_keys),
+        };
+        load.check(database.max_connections > 0, || "database.max_connections - has to be at least 1".to_string());
+        load.check(database.min_connections <= database.max_connections, || {
+            "database.min_connections - can't be more than database.max_connections".to_string()
+        });
+
+        let backups = BackupSettings::default();
+        let hours = load.or("CYBERBUN_BACKUP_HOURS", file.backups.hours, backups.interval.map_or(0, |i| i.as_secs() / (60 * 60)));
+        let backups = BackupSettings {
+            dir: load.or("CYBERBUN_BACKUP_DIR", file.backups.dir, backups.dir),
+            interval: (hours > 0).then(|| Duration::from_secs(hours * 60 * 60)),
+            keep: load.or("CYBERBUN_BACKUP_KEEP", file.backups.keep, backups.keep),
+        };
+        load.check(backups.keep > 0, || "backups.keep - has to be at least 1".to_string());
+
+        const DAY: u64 = 24 * 60 * 60;
+        let retention = RetentionSettings::default();
+        let days = load.or("CYBERBUN_RETENTION_DAYS", file.retention.days, retention.keep_for.map_or(0, |k| k.as_secs() / DAY));
+        let grace_days = load.or("CYBERBUN_GUILD_GRACE_DAYS", file.retention.guild_grace_days, retention.guild_grace.as_secs() / DAY);
+        let retention = RetentionSettings {
+            keep_for: (days > 0).then(|| Duration::from_secs(days * DAY)),
+            guild_grace: Duration::from_secs(grace_days * DAY),
+        };
+
+        if !load.problems.is_empty() {
+            return Err(ConfigErrors(load.problems));
+        }
+
+        Ok(Config {
+            token,
+            filter,
+            prefix,
+            steal_gif,
+            reminder_interval: Duration::from_secs(reminder_secs),
+            default_stars,
+            database_url,
+            database,
+            backups,
+            retention,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqliteJournalMode;
+
+    fn env(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |var| vars.iter().find(|(name, _)| *name == var).map(|(_, value)| value.to_string())
+    }
+
+    #[test]
+    fn environment_wins_over_the_file() {
+        let file = r#"
+            token = "file.bot.token"
+            prefix = "!"
+
+            [database]
+            url = "sqlite://cyberbun.db"
+            journal_mode = "delete"
+
+            [backups]
+            hours = 0
+        "#;
+
+        let config = Config::from_sources(Some(file), &env(&[("CYBERBUN_PREFIX", "?"), ("CYBERBUN_DB_MAX_CONNECTIONS", "4")])).unwrap();
+
+        assert_eq!(config.token, "file.bot.token");
+        assert_eq!(config.prefix, "?");
+        assert_eq!(config.database.max_connections, 4);
+        assert_eq!(config.database.journal_mode, SqliteJournalMode::Delete);
+        assert!(config.backups.interval.is_none());
+        assert_eq!(config.default_stars, 3);
+        assert_eq!(config.reminder_interval, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let file = r#"
+            [database]
+            url = "mysql://localhost/cyberbun"
+            synchronous = "sometimes"
+        "#;
+
+        let errors = Config::from_sources(Some(file), &env(&[("CYBERBUN_DB_MAX_CONNECTIONS", "lots"), ("CYBERBUN_DEFAULT_STARS", "0")]))
+            .err()
+            .unwrap();
+
+        assert_eq!(errors.0.len(), 5, "{}", errors);
+        assert!(errors.0[0].starts_with("token is missing"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let errors = Config::from_sources(Some("tokne = \"typo\""), &env(&[])).err().unwrap();
+
+        assert!(errors.0[0].contains("tokne"), "{}", errors);
+    }
+
+    #[test]
+    fn malformed_token_is_reported() {
+        let errors = Config::from_sources(Some("token = \"not-a-token\""), &env(&[])).err().unwrap();
+
+        assert!(errors.0[0].starts_with("token - is not a valid bot token"), "{}", errors);
+    }
+}
@@ -0,0 +1,523 @@
+//! The database behind the bot - SQLite or Postgres, picked by the scheme of `CYBERBUN_DB_URL`.
+//! Queries are written once and run on either engine. Placeholders use the `$1` form, which both of them understand.
+//! The repos take a `DbConn` instead of having a trait with an implementation per engine - the SQL is the same on both,
+//! so a single implementation keeps the engines from drifting apart. CI runs every repo test against both.
+
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use sqlx::database::HasArguments;
+use sqlx::pool::{PoolConnection, PoolOptions};
+use sqlx::postgres::{PgConnection, PgPool, PgRow};
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqliteRow, SqliteSynchronous};
+use sqlx::{Arguments, Database, Encode, Executor, FromRow, Postgres, Sqlite, Transaction, Type};
+
+use super::super::Error;
+
+/// The database engines the bot can run on
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub fn from_url(url: &str) -> Result<Self, Error> {
+        match url.split(':').next() {
+            Some("sqlite") => Ok(Backend::Sqlite),
+            Some("postgres" | "postgresql") => Ok(Backend::Postgres),
+            // Only the scheme, the rest of the url can hold a password
+            scheme => Err(format!("Unsupported database scheme {:?}, expected sqlite: or postgres://", scheme.unwrap_or_default()).into()),
+        }
+    }
+}
+
+/// How the pool and its connections are set up, the `[database]` section of the config.
+/// The journal mode, synchronous level, busy timeout and foreign keys only apply to SQLite.
+#[derive(Clone, Debug)]
+pub struct DbSettings {
+    pub max_connections: u32,
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing
+    pub acquire_timeout: Duration,
+    /// How long SQLite retries a locked database before giving up with `database is locked`
+    pub busy_timeout: Duration,
+    pub journal_mode: SqliteJournalMode,
+    pub synchronous: SqliteSynchronous,
+    pub foreign_keys: bool,
+}
+
+impl Default for DbSettings {
+    fn default() -> Self {
+        // WAL lets readers carry on while a write is going, NORMAL is safe under WAL and skips most fsyncs
+        DbSettings {
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            busy_timeout: Duration::from_secs(5),
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Normal,
+            foreign_keys: true,
+        }
+    }
+}
+
+impl DbSettings {
+    fn sqlite_options(&self, url: &str) -> Result<SqliteConnectOptions, Error> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true)
+            .journal_mode(self.journal_mode)
+            .synchronous(self.synchronous)
+            .busy_timeout(self.busy_timeout)
+            .foreign_keys(self.foreign_keys);
+
+        Ok(options)
+    }
+
+    fn pool_options<DB: Database>(&self) -> PoolOptions<DB> {
+        PoolOptions::new()
+            .max_connections(self.max_connections)
+            .min_connections(self.min_connections)
+            .acquire_timeout(self.acquire_timeout)
+    }
+}
+
+/// The connections to the database, cheap to clone - every clone shares the same connections and statistics
+#[derive(Clone, Debug)]
+pub struct DbPool {
+    pool: Pool,
+    waits: Arc<WaitStats>,
+}
+
+#[derive(Clone, Debug)]
+enum Pool {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+/// Time spent waiting for a free connection, in microseconds
+#[derive(Default, Debug)]
+struct WaitStats {
+    acquires: AtomicU64,
+    total: AtomicU64,
+    longest: AtomicU64,
+}
+
+impl WaitStats {
+    fn record(&self, started: Instant) {
+        let waited = started.elapsed().as_micros() as u64;
+
+        self.acquires.fetch_add(1, Ordering::Relaxed);
+        self.total.fetch_add(waited, Ordering::Relaxed);
+        self.longest.fetch_max(waited, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the pool, for the owner diagnostics
+#[derive(Clone, Debug)]
+pub struct PoolStats {
+    pub backend: Backend,
+    pub max_connections: u32,
+    /// Open connections, both idle and in use
+    pub size: u32,
+    pub idle: usize,
+    pub in_use: usize,
+    /// Connections handed out since the bot started
+    pub acquires: u64,
+    pub average_wait: Duration,
+    pub longest_wait: Duration,
+}
+
+impl DbPool {
+    /// Connects to the database behind `url`, a missing SQLite file gets created
+    pub async fn connect(url: &str, settings: &DbSettings) -> Result<Self, Error> {
+        let pool = match Backend::from_url(url)? {
+            Backend::Sqlite => Pool::Sqlite(settings.pool_options().connect_with(settings.sqlite_options(url)?).await?),
+            Backend::Postgres => Pool::Postgres(settings.pool_options().connect(url).await?),
+        };
+
+        Ok(DbPool { pool, waits: Arc::default() })
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self.pool {
+            Pool::Sqlite(_) => Backend::Sqlite,
+            Pool::Postgres(_) => Backend::Postgres,
+        }
+    }
+
+    /// The SQLite pool underneath, None on Postgres
+    pub fn sqlite(&self) -> Option<&SqlitePool> {
+        match &self.pool {
+            Pool::Sqlite(pool) => Some(pool),
+            Pool::Postgres(_) => None,
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<DbPoolConnection, Error> {
+        let started = Instant::now();
+        let conn = match &self.pool {
+            Pool::Sqlite(pool) => DbPoolConnection::Sqlite(pool.acquire().await?),
+            Pool::Postgres(pool) => DbPoolConnection::Postgres(pool.acquire().await?),
+        };
+        self.waits.record(started);
+
+        Ok(conn)
+    }
+
+    pub async fn begin(&self) -> Result<DbTransaction, Error> {
+        let started = Instant::now();
+        let tx = match &self.pool {
+            Pool::Sqlite(pool) => DbTransaction::Sqlite(pool.begin().await?),
+            Pool::Postgres(pool) => DbTransaction::Postgres(pool.begin().await?),
+        };
+        self.waits.record(started);
+
+        Ok(tx)
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let (size, idle, max_connections) = match &self.pool {
+            Pool::Sqlite(pool) => (pool.size(), pool.num_idle(), pool.options().get_max_connections()),
+            Pool::Postgres(pool) => (pool.size(), pool.num_idle(), pool.options().get_max_connections()),
+        };
+
+        let acquires = self.waits.acquires.load(Ordering::Relaxed);
+        let total = self.waits.total.load(Ordering::Relaxed);
+
+        PoolStats {
+            backend: self.backend(),
+            max_connections,
+            size,
+            idle,
+            in_use: (size as usize).saturating_sub(idle),
+            acquires,
+            average_wait: Duration::from_micros(total.checked_div(acquires).unwrap_or(0)),
+            longest_wait: Duration::from_micros(self.waits.longest.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl From<SqlitePool> for DbPool {
+    fn from(pool: SqlitePool) -> Self {
+        DbPool { pool: Pool::Sqlite(pool), waits: Arc::default() }
+    }
+}
+
+impl From<PgPool> for DbPool {
+    fn from(pool: PgPool) -> Self {
+        DbPool { pool: Pool::Postgres(pool), waits: Arc::default() }
+    }
+}
+
+/// A connection borrowed from a [`DbPool`], it goes back to the pool when dropped
+// Only ever held for a few queries, not worth boxing the bigger Postgres side
+#[allow(clippy::large_enum_variant)]
+pub enum DbPoolConnection {
+    Sqlite(PoolConnection<Sqlite>),
+    Postgres(PoolConnection<Postgres>),
+}
+
+/// Rolls back when dropped without a commit
+#[allow(clippy::large_enum_variant)]
+pub enum DbTransaction {
+    Sqlite(Transaction<'static, Sqlite>),
+    Postgres(Transaction<'static, Postgres>),
+}
+
+impl DbTransaction {
+    pub async fn commit(self) -> Result<(), Error> {
+        match self {
+            DbTransaction::Sqlite(tx) => tx.commit().await?,
+            DbTransaction::Postgres(tx) => tx.commit().await?,
+        }
+
+        Ok(())
+    }
+
+    pub async fn rollback(self) -> Result<(), Error> {
+        match self {
+            DbTransaction::Sqlite(tx) => tx.rollback().await?,
+            DbTransaction::Postgres(tx) => tx.rollback().await?,
+        }
+
+        Ok(())
+    }
+}
+
+/// What the repos run their queries on - a pooled connection, an open transaction or a plain connection
+pub enum DbConn<'c> {
+    Sqlite(&'c mut SqliteConnection),
+    Postgres(&'c mut PgConnection),
+}
+
+impl DbConn<'_> {
+    pub fn backend(&self) -> Backend {
+        match self {
+            DbConn::Sqlite(_) => Backend::Sqlite,
+            DbConn::Postgres(_) => Backend::Postgres,
+        }
+    }
+
+    /// Runs several statements separated by `;` at once, they can't take any parameters
+    pub async fn execute_script(&mut self, sql: &str) -> Result<(), Error> {
+        match self {
+            DbConn::Sqlite(conn) => { (**conn).execute(sql).await?; }
+            DbConn::Postgres(conn) => { (**conn).execute(sql).await?; }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'c> From<&'c mut DbPoolConnection> for DbConn<'c> {
+    fn from(conn: &'c mut DbPoolConnection) -> Self {
+        match conn {
+            DbPoolConnection::Sqlite(conn) => DbConn::Sqlite(conn),
+            DbPoolConnection::Postgres(conn) => DbConn::Postgres(conn),
+        }
+    }
+}
+
+impl<'c> From<&'c mut DbTransaction> for DbConn<'c> {
+    fn from(tx: &'c mut DbTransaction) -> Self {
+        match tx {
+            DbTransaction::Sqlite(tx) => DbConn::Sqlite(tx),
+            DbTransaction::Postgres(tx) => DbConn::Postgres(tx),
+        }
+    }
+}
+
+impl<'c> From<&'c mut SqliteConnection> for DbConn<'c> {
+    fn from(conn: &'c mut SqliteConnection) -> Self {
+        DbConn::Sqlite(conn)
+    }
+}
+
+impl<'c> From<&'c mut PgConnection> for DbConn<'c> {
+    fn from(conn: &'c mut PgConnection) -> Self {
+        DbConn::Postgres(conn)
+    }
+}
+
+/// A value bound to a query. Nulls keep their type, Postgres wants to know it.
+pub enum Value {
+    Int(Option<i64>),
+    Bool(Option<bool>),
+    Text(Option<String>),
+}
+
+/// Anything that can be bound to a query on every backend
+pub trait Bind {
+    fn value(self) -> Value;
+
+    /// The null of this type
+    fn null() -> Value;
+}
+
+macro_rules! bind_int {
+    ($($int:ty),*) => {
+        $(
+            impl Bind for $int {
+                fn value(self) -> Value {
+                    Value::Int(Some(self as i64))
+                }
+
+                fn null() -> Value {
+                    Value::Int(None)
+                }
+            }
+        )*
+    };
+}
+
+bind_int!(u8, u32, i64);
+
+impl Bind for bool {
+    fn value(self) -> Value {
+        Value::Bool(Some(self))
+    }
+
+    fn null() -> Value {
+        Value::Bool(None)
+    }
+}
+
+impl Bind for String {
+    fn value(self) -> Value {
+        Value::Text(Some(self))
+    }
+
+    fn null() -> Value {
+        Value::Text(None)
+    }
+}
+
+impl Bind for &str {
+    fn value(self) -> Value {
+        Value::Text(Some(self.to_string()))
+    }
+
+    fn null() -> Value {
+        Value::Text(None)
+    }
+}
+
+impl<T: Bind> Bind for Option<T> {
+    fn value(self) -> Value {
+        match self {
+            Some(value) => value.value(),
+            None => T::null(),
+        }
+    }
+
+    fn null() -> Value {
+        T::null()
+    }
+}
+
+/// Rows that can be read from every backend
+pub trait FromDbRow: for<'r> FromRow<'r, SqliteRow> + for<'r> FromRow<'r, PgRow> + Send + Unpin {}
+
+impl<T> FromDbRow for T where T: for<'r> FromRow<'r, SqliteRow> + for<'r> FromRow<'r, PgRow> + Send + Unpin {}
+
+pub fn query(sql: &str) -> Query<'_> {
+    Query { sql, values: vec![] }
+}
+
+pub fn query_as<T: FromDbRow>(sql: &str) -> QueryAs<'_, T> {
+    QueryAs { query: query(sql), row: PhantomData }
+}
+
+pub struct Query<'q> {
+    sql: &'q str,
+    values: Vec<Value>,
+}
+
+impl<'q> Query<'q> {
+    pub fn bind(mut self, value: impl Bind) -> Self {
+        self.values.push(value.value());
+        self
+    }
+
+    pub async fn execute(self, conn: &mut DbConn<'_>) -> Result<QueryResult, Error> {
+        let rows_affected = match conn {
+            DbConn::Sqlite(conn) => sqlx::query_with(self.sql, arguments::<Sqlite>(self.values)).execute(&mut **conn).await?.rows_affected(),
+            DbConn::Postgres(conn) => sqlx::query_with(self.sql, arguments::<Postgres>(self.values)).execute(&mut **conn).await?.rows_affected(),
+        };
+
+        Ok(QueryResult { rows_affected })
+    }
+}
+
+pub struct QueryResult {
+    rows_affected: u64,
+}
+
+impl QueryResult {
+    pub fn rows_affected(&self) -> u64 {
+        self.rows_affected
+    }
+}
+
+pub struct QueryAs<'q, T> {
+    query: Query<'q>,
+    row: PhantomData<T>,
+}
+
+impl<'q, T: FromDbRow> QueryAs<'q, T> {
+    pub fn bind(mut self, value: impl Bind) -> Self {
+        self.query = self.query.bind(value);
+        self
+    }
+
+    pub async fn fetch_one(self, conn: &mut DbConn<'_>) -> Result<T, Error> {
+        let Query { sql, values } = self.query;
+
+        let result = match conn {
+            DbConn::Sqlite(conn) => sqlx::query_as_with(sql, arguments::<Sqlite>(values)).fetch_one(&mut **conn).await?,
+            DbConn::Postgres(conn) => sqlx::query_as_with(sql, arguments::<Postgres>(values)).fetch_one(&mut **conn).await?,
+        };
+
+        Ok(result)
+    }
+
+    pub async fn fetch_optional(self, conn: &mut DbConn<'_>) -> Result<Option<T>, Error> {
+        let Query { sql, values } = self.query;
+
+        let result = match conn {
+            DbConn::Sqlite(conn) => sqlx::query_as_with(sql, arguments::<Sqlite>(values)).fetch_optional(&mut **conn).await?,
+            DbConn::Postgres(conn) => sqlx::query_as_with(sql, arguments::<Postgres>(values)).fetch_optional(&mut **conn).await?,
+        };
+
+        Ok(result)
+    }
+
+    pub async fn fetch_all(self, conn: &mut DbConn<'_>) -> Result<Vec<T>, Error> {
+        let Query { sql, values } = self.query;
+
+        let result = match conn {
+            DbConn::Sqlite(conn) => sqlx::query_as_with(sql, arguments::<Sqlite>(values)).fetch_all(&mut **conn).await?,
+            DbConn::Postgres(conn) => sqlx::query_as_with(sql, arguments::<Postgres>(values)).fetch_all(&mut **conn).await?,
+        };
+
+        Ok(result)
+    }
+}
+
+fn arguments<'q, DB: Database>(values: Vec<Value>) -> <DB as HasArguments<'q>>::Arguments
+where
+    Option<i64>: Encode<'q, DB> + Type<DB>,
+    Option<bool>: Encode<'q, DB> + Type<DB>,
+    Option<String>: Encode<'q, DB> + Type<DB>,
+{
+    let mut arguments = <DB as HasArguments<'q>>::Arguments::default();
+
+    for value in values {
+        match value {
+            Value::Int(value) => arguments.add(value),
+            Value::Bool(value) => arguments.add(value),
+            Value::Text(value) => arguments.add(value),
+        }
+    }
+
+    arguments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sqlite_settings_are_applied() {
+        let path = std::env::temp_dir().join(format!("cyberbun-settings-{}.db", std::process::id()));
+        let settings = DbSettings { busy_timeout: Duration::from_millis(1234), ..DbSettings::default() };
+
+        let pool = DbPool::connect(&format!("sqlite://{}", path.display()), &settings).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let db = &mut DbConn::from(&mut conn);
+
+        let (journal_mode,) = query_as::<(String,)>("PRAGMA journal_mode;").fetch_one(db).await.unwrap();
+        let (synchronous,) = query_as::<(i64,)>("PRAGMA synchronous;").fetch_one(db).await.unwrap();
+        let (busy_timeout,) = query_as::<(i64,)>("PRAGMA busy_timeout;").fetch_one(db).await.unwrap();
+        let (foreign_keys,) = query_as::<(bool,)>("PRAGMA foreign_keys;").fetch_one(db).await.unwrap();
+
+        assert_eq!(journal_mode, "wal");
+        assert_eq!(synchronous, 1);
+        assert_eq!(busy_timeout, 1234);
+        assert!(foreign_keys);
+
+        let stats = pool.stats();
+        assert_eq!((stats.acquires, stats.in_use, stats.max_connections), (1, 1, 10));
+
+        // Closing waits for every connection to come back to the pool
+        drop(conn);
+        pool.sqlite().unwrap().close().await;
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -1,48 +1,425 @@
-use sqlx::{pool::PoolConnection, Sqlite};
-use tracing::info;
+use tracing::{info, warn};
 
 use super::super::Error;
+use super::backend::{query, query_as, Backend, DbConn};
 
-pub async fn build_colors(mut conn: PoolConnection<Sqlite>) -> Result<(), Error> {
-    let result = sqlx::query(
+/// Key of the Postgres advisory lock held while the tables are built
+const BUILD_LOCK: i64 = 0x6379_6265_7262_756e;
+
+/// Makes sure every table exists and is up to date.
+/// The tables are the same on every backend, only ids that count up and the triggers are written per backend.
+pub async fn build_tables<'c>(conn: impl Into<DbConn<'c>>) -> Result<(), Error> {
+    let conn = &mut conn.into();
+
+    // Shards starting at the same time would trip over each other's changes
+    if conn.backend() == Backend::Postgres {
+        query("SELECT pg_advisory_lock($1);").bind(BUILD_LOCK).execute(conn).await?;
+    }
+
+    let result = build_all(conn).await;
+
+    if conn.backend() == Backend::Postgres {
+        query("SELECT pg_advisory_unlock($1);").bind(BUILD_LOCK).execute(conn).await?;
+    }
+
+    result
+}
+
+async fn build_all(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    build_abandoned_roles(conn).await?;
+    build_colors(conn).await?;
+    build_color_rules(conn).await?;
+    build_color_members(conn).await?;
+    build_color_history(conn).await?;
+    build_color_jobs(conn).await?;
+    build_color_cycles(conn).await?;
+    build_guild_settings(conn).await?;
+    build_color_settings(conn).await?;
+    build_starred_messages(conn).await?;
+    build_reminders(conn).await?;
+    build_left_guilds(conn).await?;
+
+    Ok(())
+}
+
+pub async fn build_colors(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    let result = query(
         r#"
         CREATE TABLE IF NOT EXISTS colors (
-            role_id BIG INT PRIMARY KEY NOT NULL,
-            uid BIG INT NOT NULL,
-            guid BIG INT NOT NULL,
-            color INT NOT NULL,
-            role_name TEXT NOT NULL
+            role_id BIGINT PRIMARY KEY NOT NULL,
+            uid BIGINT NOT NULL,
+            guid BIGINT NOT NULL,
+            color BIGINT NOT NULL,
+            role_name TEXT NOT NULL,
+            pending_color BIGINT
         );
         "#
-    ).execute(&mut *conn)
+    ).execute(conn)
     .await?;
 
-    conn.close().await?;
-
     match result.rows_affected() {
         0 => info!("Colors Database already exists"),
         _ => info!("Colors Database created successfully."),
     }
 
+    // The color the bot is giving the role while discord has not answered yet
+    add_missing_column(conn, "colors", "pending_color", "BIGINT").await?;
+
+    // Older databases could end up with more than one color per member, keep the newest role (snowflakes grow over time).
+    // The roles of the other rows are still in their guild, the audit finds them as orphaned and deletes them.
+    query(
+        r#"
+        INSERT INTO abandoned_roles (role_id, guid)
+        SELECT role_id, guid FROM colors
+        WHERE role_id NOT IN (SELECT MAX(role_id) FROM colors GROUP BY uid, guid)
+        ON CONFLICT (role_id) DO NOTHING;
+        "#
+    ).execute(conn)
+    .await?;
+
+    let removed = query(
+        r#"
+        DELETE FROM colors
+        WHERE role_id NOT IN (SELECT MAX(role_id) FROM colors GROUP BY uid, guid);
+        "#
+    ).execute(conn)
+    .await?;
+
+    if removed.rows_affected() != 0 {
+        warn!("Removed {} duplicate color row(s)", removed.rows_affected());
+    }
+
+    // One color per member - guild first so it also serves the lookups of a whole guild
+    query(
+        r#"
+        CREATE UNIQUE INDEX IF NOT EXISTS colors_guild_member ON colors (guid, uid);
+        "#
+    ).execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn build_abandoned_roles(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Color roles the bot replaced with a new one while the old one might still exist, the audit deletes them
+
+    let result = query(
+        r#"
+        CREATE TABLE IF NOT EXISTS abandoned_roles (
+            role_id BIGINT PRIMARY KEY NOT NULL,
+            guid BIGINT NOT NULL
+        );
+        "#
+    ).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Abandoned roles Database already exists"),
+        _ => info!("Abandoned roles Database created successfully."),
+    }
+
     Ok(())
 }
 
-pub async fn build_guild_settings(mut conn: PoolConnection<Sqlite>) -> Result<(), Error> {
+pub async fn build_color_rules(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Who is allowed to have a color role - roles that are required and users that are denied
+
+    let result = query(
+        r#"
+        CREATE TABLE IF NOT EXISTS color_rules (
+            guild_id BIGINT NOT NULL,
+            kind TEXT NOT NULL,
+            target BIGINT NOT NULL,
+            PRIMARY KEY (guild_id, kind, target)
+        );
+        "#
+    ).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Color rules Database already exists"),
+        _ => info!("Color rules Database created successfully."),
+    }
+
+    Ok(())
+}
+
+pub async fn build_color_members(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Per member color preferences - eg protection against stealing and when they last stole a color
+
+    let result = query(
+        r#"
+        CREATE TABLE IF NOT EXISTS color_members (
+            uid BIGINT NOT NULL,
+            guid BIGINT NOT NULL,
+            steal_protection BIGINT NOT NULL DEFAULT 0,
+            last_steal BIGINT,
+            PRIMARY KEY (uid, guid)
+        );
+        "#
+    ).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Color members Database already exists"),
+        _ => info!("Color members Database created successfully."),
+    }
+
+    Ok(())
+}
+
+pub async fn build_color_history(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    let result = query(&format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS color_history (
+            id {},
+            uid BIGINT NOT NULL,
+            guid BIGINT NOT NULL,
+            old_color BIGINT,
+            color BIGINT NOT NULL,
+            source TEXT NOT NULL,
+            timestamp BIGINT NOT NULL
+        );
+        "#,
+        serial_id(conn)
+    )).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Color history Database already exists"),
+        _ => info!("Color history Database created successfully."),
+    }
+
+    query(
+        r#"
+        CREATE INDEX IF NOT EXISTS color_history_member ON color_history (guid, uid, id);
+        "#
+    ).execute(conn)
+    .await?;
+
+    // The history is about a member's color role and goes with it, like cycles and reverts
+    query(
+        r#"
+        DELETE FROM color_history
+        WHERE NOT EXISTS (SELECT 1 FROM colors WHERE colors.uid = color_history.uid AND colors.guid = color_history.guid);
+        "#
+    ).execute(conn)
+    .await?;
+
+    let triggers = match conn.backend() {
+        Backend::Sqlite => r#"
+        CREATE TRIGGER IF NOT EXISTS color_history_cleanup
+        AFTER DELETE ON colors
+        BEGIN
+            DELETE FROM color_history WHERE uid = OLD.uid AND guid = OLD.guid;
+        END;
+        "#,
+        Backend::Postgres => r#"
+        CREATE OR REPLACE FUNCTION color_history_cleanup() RETURNS trigger AS $$
+        BEGIN
+            DELETE FROM color_history WHERE uid = OLD.uid AND guid = OLD.guid;
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+
+        CREATE OR REPLACE TRIGGER color_history_cleanup
+        AFTER DELETE ON colors
+        FOR EACH ROW EXECUTE FUNCTION color_history_cleanup();
+        "#,
+    };
+
+    conn.execute_script(triggers).await?;
+
+    Ok(())
+}
+
+pub async fn build_color_cycles(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Members whose color role cycles through a list of colors - base_color is what they go back to when it stops
+
+    let result = query(
+        r#"
+        CREATE TABLE IF NOT EXISTS color_cycles (
+            uid BIGINT NOT NULL,
+            guid BIGINT NOT NULL,
+            colors TEXT NOT NULL,
+            interval BIGINT NOT NULL,
+            step BIGINT NOT NULL DEFAULT 0,
+            base_color BIGINT NOT NULL,
+            next_run BIGINT NOT NULL,
+            PRIMARY KEY (uid, guid)
+        );
+        "#
+    ).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Color cycles Database already exists"),
+        _ => info!("Color cycles Database created successfully."),
+    }
+
+    // A cycle changes the member's color role, so it can't outlive it
+    conn.execute_script(
+        r#"
+        DELETE FROM color_cycles
+        WHERE NOT EXISTS (SELECT 1 FROM colors WHERE colors.uid = color_cycles.uid AND colors.guid = color_cycles.guid);
+
+        CREATE INDEX IF NOT EXISTS color_cycles_next_run ON color_cycles (next_run);
+        "#
+    ).await?;
+
+    let triggers = match conn.backend() {
+        Backend::Sqlite => r#"
+        CREATE TRIGGER IF NOT EXISTS color_cycles_need_color
+        BEFORE INSERT ON color_cycles
+        WHEN NOT EXISTS (SELECT 1 FROM colors WHERE uid = NEW.uid AND guid = NEW.guid)
+        BEGIN
+            SELECT RAISE(ABORT, 'color cycle for a member without a color role');
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS color_cycles_cleanup
+        AFTER DELETE ON colors
+        BEGIN
+            DELETE FROM color_cycles WHERE uid = OLD.uid AND guid = OLD.guid;
+        END;
+        "#,
+        Backend::Postgres => r#"
+        CREATE OR REPLACE FUNCTION color_cycles_need_color() RETURNS trigger AS $$
+        BEGIN
+            IF NOT EXISTS (SELECT 1 FROM colors WHERE uid = NEW.uid AND guid = NEW.guid) THEN
+                RAISE EXCEPTION 'color cycle for a member without a color role';
+            END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+
+        CREATE OR REPLACE TRIGGER color_cycles_need_color
+        BEFORE INSERT ON color_cycles
+        FOR EACH ROW EXECUTE FUNCTION color_cycles_need_color();
+
+        CREATE OR REPLACE FUNCTION color_cycles_cleanup() RETURNS trigger AS $$
+        BEGIN
+            DELETE FROM color_cycles WHERE uid = OLD.uid AND guid = OLD.guid;
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+
+        CREATE OR REPLACE TRIGGER color_cycles_cleanup
+        AFTER DELETE ON colors
+        FOR EACH ROW EXECUTE FUNCTION color_cycles_cleanup();
+        "#,
+    };
+
+    conn.execute_script(triggers).await?;
+
+    Ok(())
+}
+
+pub async fn build_color_jobs(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Scheduled color changes - temporary colors that need reverting and guild wide color themes
+
+    let result = query(&format!(
+        r#"
+        CREATE TABLE IF NOT EXISTS color_jobs (
+            id {},
+            kind TEXT NOT NULL,
+            guid BIGINT NOT NULL,
+            uid BIGINT,
+            color BIGINT,
+            theme TEXT,
+            duration BIGINT,
+            run_at BIGINT NOT NULL
+        );
+        "#,
+        serial_id(conn)
+    )).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Color jobs Database already exists"),
+        _ => info!("Color jobs Database created successfully."),
+    }
+
+    // A member has at most one pending revert, the oldest one holds the color to go back to.
+    // Like cycles, reverts belong to a color role and go when it does.
+    conn.execute_script(
+        r#"
+        DELETE FROM color_jobs
+        WHERE kind = 'revert' AND (
+            id NOT IN (SELECT MIN(id) FROM color_jobs WHERE kind = 'revert' GROUP BY guid, uid)
+            OR NOT EXISTS (SELECT 1 FROM colors WHERE colors.uid = color_jobs.uid AND colors.guid = color_jobs.guid)
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS color_jobs_revert ON color_jobs (guid, uid) WHERE kind = 'revert';
+        CREATE INDEX IF NOT EXISTS color_jobs_guild ON color_jobs (guid, kind);
+        CREATE INDEX IF NOT EXISTS color_jobs_run_at ON color_jobs (run_at);
+        "#
+    ).await?;
+
+    let triggers = match conn.backend() {
+        Backend::Sqlite => r#"
+        CREATE TRIGGER IF NOT EXISTS color_jobs_revert_needs_color
+        BEFORE INSERT ON color_jobs
+        WHEN NEW.kind = 'revert' AND NOT EXISTS (SELECT 1 FROM colors WHERE uid = NEW.uid AND guid = NEW.guid)
+        BEGIN
+            SELECT RAISE(ABORT, 'color revert for a member without a color role');
+        END;
+
+        CREATE TRIGGER IF NOT EXISTS color_jobs_revert_cleanup
+        AFTER DELETE ON colors
+        BEGIN
+            DELETE FROM color_jobs WHERE kind = 'revert' AND uid = OLD.uid AND guid = OLD.guid;
+        END;
+        "#,
+        Backend::Postgres => r#"
+        CREATE OR REPLACE FUNCTION color_jobs_revert_needs_color() RETURNS trigger AS $$
+        BEGIN
+            IF NEW.kind = 'revert' AND NOT EXISTS (SELECT 1 FROM colors WHERE uid = NEW.uid AND guid = NEW.guid) THEN
+                RAISE EXCEPTION 'color revert for a member without a color role';
+            END IF;
+            RETURN NEW;
+        END;
+        $$ LANGUAGE plpgsql;
+
+        CREATE OR REPLACE TRIGGER color_jobs_revert_needs_color
+        BEFORE INSERT ON color_jobs
+        FOR EACH ROW EXECUTE FUNCTION color_jobs_revert_needs_color();
+
+        CREATE OR REPLACE FUNCTION color_jobs_revert_cleanup() RETURNS trigger AS $$
+        BEGIN
+            DELETE FROM color_jobs WHERE kind = 'revert' AND uid = OLD.uid AND guid = OLD.guid;
+            RETURN NULL;
+        END;
+        $$ LANGUAGE plpgsql;
+
+        CREATE OR REPLACE TRIGGER color_jobs_revert_cleanup
+        AFTER DELETE ON colors
+        FOR EACH ROW EXECUTE FUNCTION color_jobs_revert_cleanup();
+        "#,
+    };
+
+    conn.execute_script(triggers).await?;
+
+    Ok(())
+}
+
+pub async fn build_guild_settings(conn: &mut DbConn<'_>) -> Result<(), Error> {
     // Table for all the guild related settings - eg Starboard enabled, Starboard channel, ...
 
-    let result = sqlx::query(
+    let result = query(
         r#"
         CREATE TABLE IF NOT EXISTS guild_settings (
-            guild_id BIG INT PRIMARY KEY NOT NULL,
+            guild_id BIGINT PRIMARY KEY NOT NULL,
             starboard_enabled BOOLEAN NOT NULL,
-            starboard_channel BIG INT,
-            starboard_min SMALL INT NOT NULL
+            starboard_channel BIGINT,
+            starboard_min BIGINT NOT NULL,
+            prefix TEXT
         );
         "#)
-        .execute(&mut *conn)
+        .execute(conn)
         .await?;
 
-    conn.close().await?;
+    // Text command prefix of the guild, empty uses the configured one
+    add_missing_column(conn, "guild_settings", "prefix", "TEXT").await?;
     
     match result.rows_affected() {
         0 => info!("Guild settings Database already exists"),
@@ -53,18 +430,64 @@ pub async fn build_guild_settings(mut conn: PoolConnection<Sqlite>) -> Result<()
     Ok(())
 }
 
-pub async fn build_starred_messages(mut conn: PoolConnection<Sqlite>) -> Result<(), Error> {
+pub async fn build_color_settings(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Color role related settings per guild - eg the anchor role color roles get placed under
+
+    let result = query(
+        r#"
+        CREATE TABLE IF NOT EXISTS color_settings (
+            guild_id BIGINT PRIMARY KEY NOT NULL,
+            anchor_role BIGINT,
+            remove_on_leave BOOLEAN NOT NULL DEFAULT FALSE,
+            steal_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+            steal_cooldown BIGINT NOT NULL DEFAULT 0,
+            naming_template TEXT,
+            min_member_age BIGINT NOT NULL DEFAULT 0,
+            booster_only BOOLEAN NOT NULL DEFAULT FALSE,
+            log_channel BIGINT
+        );
+        "#)
+        .execute(conn)
+        .await?;
+
+    // Columns added after the table was first released
+    add_missing_column(conn, "color_settings", "remove_on_leave", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    add_missing_column(conn, "color_settings", "steal_enabled", "BOOLEAN NOT NULL DEFAULT TRUE").await?;
+    add_missing_column(conn, "color_settings", "steal_cooldown", "BIGINT NOT NULL DEFAULT 0").await?;
+    add_missing_column(conn, "color_settings", "naming_template", "TEXT").await?;
+    add_missing_column(conn, "color_settings", "min_member_age", "BIGINT NOT NULL DEFAULT 0").await?;
+    add_missing_column(conn, "color_settings", "booster_only", "BOOLEAN NOT NULL DEFAULT FALSE").await?;
+    add_missing_column(conn, "color_settings", "log_channel", "BIGINT").await?;
+
+    match result.rows_affected() {
+        0 => info!("Color settings Database already exists"),
+        _ => info!("Color settings Database created successfully."),
+    }
+
+    Ok(())
+}
+
+pub async fn build_starred_messages(conn: &mut DbConn<'_>) -> Result<(), Error> {
 
-    let result = sqlx::query(
+    let result = query(
         r#"
         CREATE TABLE IF NOT EXISTS starred_messages (
-            msg_id BIG INT PRIMARY KEY NOT NULL
+            msg_id BIGINT PRIMARY KEY NOT NULL
         );
         "#)
-        .execute(&mut *conn)
+        .execute(conn)
         .await?;
 
-    conn.close().await?;
+    // Messages starred before the guild was recorded keep an empty guild_id
+    add_missing_column(conn, "starred_messages", "guild_id", "BIGINT").await?;
+
+    // Purging a guild looks its messages up
+    query(
+        r#"
+        CREATE INDEX IF NOT EXISTS starred_messages_guild ON starred_messages (guild_id);
+        "#
+    ).execute(conn)
+    .await?;
 
     match result.rows_affected() {
         0 => info!("Starred messages Database already exists"),
@@ -74,28 +497,174 @@ pub async fn build_starred_messages(mut conn: PoolConnection<Sqlite>) -> Result<
     Ok(())
 }
 
-pub async fn build_reminders(mut conn: PoolConnection<Sqlite>) -> Result<(), Error> {
-    let result = sqlx::query(
+pub async fn build_reminders(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    let result = query(&format!(
         r#"
         CREATE TABLE IF NOT EXISTS reminders (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            timestamp BIG INT NOT NULL,
+            id {},
+            timestamp BIGINT NOT NULL,
             message TEXT NOT NULL,
-            user_id BIG INT NOT NULL,
-            channel_id BIG INT NOT NULL,
+            user_id BIGINT NOT NULL,
+            channel_id BIGINT NOT NULL,
             private BOOLEAN NOT NULL,
-            completed BOOLEAN NOT NULL
+            completed BOOLEAN NOT NULL,
+            guild_id BIGINT
         )
-        "#)
-        .execute(&mut *conn)
+        "#,
+        serial_id(conn)
+    ))
+        .execute(conn)
         .await?;
 
-    conn.close().await?;
+    // Reminders set in DMs, or before the guild was recorded, keep an empty guild_id
+    add_missing_column(conn, "reminders", "guild_id", "BIGINT").await?;
 
     match result.rows_affected() {
         0 => info!("Reminders Database already exists"),
         _ => info!("Reminders Database created successfully."),
     }
 
+    // Every poll looks for open reminders that are due
+    query(
+        r#"
+        CREATE INDEX IF NOT EXISTS reminders_due ON reminders (completed, timestamp);
+        "#
+    ).execute(conn)
+    .await?;
+
+    // Deleting the data of a user or purging a guild
+    for sql in [
+        "CREATE INDEX IF NOT EXISTS reminders_user ON reminders (user_id);",
+        "CREATE INDEX IF NOT EXISTS reminders_guild ON reminders (guild_id);",
+    ] {
+        query(sql).execute(conn).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn build_left_guilds(conn: &mut DbConn<'_>) -> Result<(), Error> {
+    // Guilds that removed the bot, their data is purged once the grace period is over
+
+    let result = query(
+        r#"
+        CREATE TABLE IF NOT EXISTS left_guilds (
+            guild_id BIGINT PRIMARY KEY NOT NULL,
+            left_at BIGINT NOT NULL
+        );
+        "#
+    ).execute(conn)
+    .await?;
+
+    match result.rows_affected() {
+        0 => info!("Left guilds Database already exists"),
+        _ => info!("Left guilds Database created successfully."),
+    }
+
+    Ok(())
+}
+
+/// The column type of an id that counts up by itself
+fn serial_id(conn: &DbConn<'_>) -> &'static str {
+    match conn.backend() {
+        Backend::Sqlite => "INTEGER PRIMARY KEY AUTOINCREMENT",
+        Backend::Postgres => "BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY",
+    }
+}
+
+/// Adds a column to an already existing table, does nothing when the column is there already
+async fn add_missing_column(conn: &mut DbConn<'_>, table: &str, column: &str, definition: &str) -> Result<(), Error> {
+    let exists = match conn.backend() {
+        Backend::Sqlite => {
+            let columns = query_as::<(String,)>("SELECT name FROM pragma_table_info($1);")
+                .bind(table)
+                .fetch_all(conn)
+                .await?;
+
+            columns.iter().any(|(name,)| name == column)
+        }
+        Backend::Postgres => {
+            let columns = query_as::<(String,)>("SELECT column_name::TEXT FROM information_schema.columns WHERE table_schema = current_schema() AND table_name = $1;")
+                .bind(table)
+                .fetch_all(conn)
+                .await?;
+
+            columns.iter().any(|(name,)| name == column)
+        }
+    };
+
+    if !exists {
+        query(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition))
+            .execute(conn)
+            .await?;
+
+        info!("Added column {} to {}", column, table);
+    }
+
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::for_each_backend;
+
+    #[tokio::test]
+    async fn duplicate_colors_keep_the_newest_role() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let conn = &mut DbConn::from(&mut conn);
+
+            // A database from before the unique index
+            query("DROP INDEX colors_guild_member;").execute(conn).await.unwrap();
+            for (role_id, uid) in [(100, 1), (300, 1), (200, 1), (400, 2)] {
+                query("INSERT INTO colors (role_id, uid, guid, color, role_name) VALUES ($1, $2, 10, 0, 'name');")
+                    .bind(role_id as i64)
+                    .bind(uid as i64)
+                    .execute(conn)
+                    .await
+                    .unwrap();
+            }
+
+            build_all(conn).await.unwrap();
+
+            let rows = query_as::<(i64, i64)>("SELECT role_id, uid FROM colors ORDER BY uid;")
+                .fetch_all(conn)
+                .await
+                .unwrap();
+            assert_eq!(rows, vec![(300, 1), (400, 2)]);
+
+            // Their roles are left for the audit to clean up
+            let abandoned = query_as::<(i64,)>("SELECT role_id FROM abandoned_roles ORDER BY role_id;")
+                .fetch_all(conn)
+                .await
+                .unwrap();
+            assert_eq!(abandoned, vec![(100,), (200,)]);
+
+            let duplicate = query("INSERT INTO colors (role_id, uid, guid, color, role_name) VALUES (500, 1, 10, 0, 'name');").execute(conn).await;
+            assert!(duplicate.is_err());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn color_history_goes_with_the_color_role() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let conn = &mut DbConn::from(&mut conn);
+
+            let history = "INSERT INTO color_history (uid, guid, color, source, timestamp) VALUES ($1, 10, 0, 'set', 0);";
+            query("INSERT INTO colors (role_id, uid, guid, color, role_name) VALUES (100, 1, 10, 0, 'name');").execute(conn).await.unwrap();
+            for uid in [1, 2] {
+                query(history).bind(uid as i64).execute(conn).await.unwrap();
+            }
+
+            // Left behind before the cleanup existed
+            build_all(conn).await.unwrap();
+            let count = "SELECT COUNT(*) FROM color_history;";
+            assert_eq!(query_as::<(i64,)>(count).fetch_one(conn).await.unwrap(), (1,));
+
+            query("DELETE FROM colors WHERE role_id = 100;").execute(conn).await.unwrap();
+            assert_eq!(query_as::<(i64,)>(count).fetch_one(conn).await.unwrap(), (0,));
+        }).await;
+    }
+}
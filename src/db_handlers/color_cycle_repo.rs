@@ -0,0 +1,175 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::Error;
+use super::ids::{DbGuildId, DbUserId};
+use super::backend::{query, query_as, DbConn};
+
+#[derive(sqlx::FromRow)]
+pub struct ColorCycle {
+    pub uid: DbUserId,
+    pub guid: DbGuildId,
+    /// Comma separated hex codes, the role goes through them in order
+    pub colors: String,
+    /// Seconds between color changes
+    pub interval: i64,
+    /// Index of the color that is up next
+    #[sqlx(try_from = "i64")]
+    pub step: u32,
+    /// Color the member had before the cycle started
+    #[sqlx(try_from = "i64")]
+    pub base_color: u32,
+}
+
+/// Members whose color role cycles through a list of colors
+pub struct ColorCycleRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> ColorCycleRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        ColorCycleRepo { conn: conn.into() }
+    }
+
+    /// Starts or replaces the cycle of a member, a replaced cycle keeps its base color
+    pub async fn start(&mut self, uid: DbUserId, guid: DbGuildId, colors: String, interval: u64, base_color: u32) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        query(
+            r#"
+            INSERT INTO color_cycles (uid, guid, colors, interval, step, base_color, next_run)
+            VALUES ($1, $2, $3, $4, 0, $5, $6)
+            ON CONFLICT (uid, guid) DO UPDATE
+            SET colors = excluded.colors, interval = excluded.interval, step = 0, next_run = excluded.next_run;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .bind(colors)
+        .bind(interval as i64)
+        .bind(base_color)
+        .bind(now as i64)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<Option<ColorCycle>, Error> {
+        let result = query_as::<ColorCycle>(
+            r#"
+            SELECT uid, guid, colors, interval, step, base_color
+            FROM color_cycles
+            WHERE uid = $1 AND guid = $2;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// The cycles that are due the longest, at most `limit` of them
+    pub async fn get_due(&mut self, limit: u32) -> Result<Vec<ColorCycle>, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        let result = query_as::<ColorCycle>(
+            r#"
+            SELECT uid, guid, colors, interval, step, base_color
+            FROM color_cycles
+            WHERE next_run <= $1
+            ORDER BY next_run
+            LIMIT $2;
+            "#,
+        )
+        .bind(now as i64)
+        .bind(limit)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn advance(&mut self, uid: DbUserId, guid: DbGuildId, step: u32, next_run: u64) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE color_cycles
+            SET step = $1, next_run = $2
+            WHERE uid = $3 AND guid = $4;
+            "#,
+        )
+        .bind(step)
+        .bind(next_run as i64)
+        .bind(uid)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns false when the member had no cycle running
+    pub async fn stop(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            DELETE FROM color_cycles
+            WHERE uid = $1 AND guid = $2;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::color_repo::ColorRepo;
+    use crate::db_handlers::ids::DbRoleId;
+    use crate::db_handlers::for_each_backend;
+
+    const GUILD: DbGuildId = DbGuildId(10);
+    const USER: DbUserId = DbUserId(20);
+
+    #[tokio::test]
+    async fn restarting_a_cycle_keeps_the_base_color() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            ColorRepo::new(&mut conn).create(DbRoleId(30), USER, GUILD, 0x123456, "one".to_string()).await.unwrap();
+            let mut repo = ColorCycleRepo::new(&mut conn);
+
+            repo.start(USER, GUILD, "ff0000,00ff00".to_string(), 300, 0x123456).await.unwrap();
+            repo.advance(USER, GUILD, 1, 0).await.unwrap();
+            repo.start(USER, GUILD, "0000ff,ffffff".to_string(), 600, 0xffffff).await.unwrap();
+
+            let cycle = repo.get(USER, GUILD).await.unwrap().unwrap();
+            assert_eq!(cycle.base_color, 0x123456);
+            assert_eq!(cycle.step, 0);
+            assert_eq!(cycle.interval, 600);
+
+            assert_eq!(repo.get_due(5).await.unwrap().len(), 1);
+            assert!(repo.stop(USER, GUILD).await.unwrap());
+            assert!(!repo.stop(USER, GUILD).await.unwrap());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn cycles_go_with_the_color_role() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+
+            assert!(ColorCycleRepo::new(&mut conn).start(USER, GUILD, "ff0000".to_string(), 300, 0).await.is_err());
+
+            ColorRepo::new(&mut conn).create(DbRoleId(30), USER, GUILD, 0, "one".to_string()).await.unwrap();
+            ColorCycleRepo::new(&mut conn).start(USER, GUILD, "ff0000".to_string(), 300, 0).await.unwrap();
+            ColorRepo::new(&mut conn).delete(DbRoleId(30)).await.unwrap();
+
+            assert!(ColorCycleRepo::new(&mut conn).get(USER, GUILD).await.unwrap().is_none());
+        }).await;
+    }
+}
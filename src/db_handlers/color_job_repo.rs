@@ -0,0 +1,327 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::Error;
+use super::ids::{DbGuildId, DbUserId};
+use super::backend::{query, query_as, Bind, DbConn, Value};
+use sqlx::{ColumnIndex, Decode, FromRow, Row, Type};
+
+/// What a job does once it is due
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum JobKind {
+    /// Sets the color of a single member back to `color`
+    Revert,
+    /// Gives every color role in the guild one of the theme colors, for `duration` seconds
+    Theme,
+}
+
+impl JobKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            JobKind::Revert => "revert",
+            JobKind::Theme => "theme",
+        }
+    }
+
+    fn from_str(kind: &str) -> Option<Self> {
+        match kind {
+            "revert" => Some(JobKind::Revert),
+            "theme" => Some(JobKind::Theme),
+            _ => None,
+        }
+    }
+}
+
+impl Bind for JobKind {
+    fn value(self) -> Value {
+        self.as_str().value()
+    }
+
+    fn null() -> Value {
+        <&str>::null()
+    }
+}
+
+pub struct ColorJob {
+    pub id: i64,
+    pub kind: JobKind,
+    pub guid: DbGuildId,
+    pub uid: Option<DbUserId>,
+    pub color: Option<u32>,
+    /// Comma separated hex codes of a theme
+    pub theme: Option<String>,
+    pub duration: Option<i64>,
+    pub run_at: i64,
+}
+
+// By hand as the derive can't turn an optional i64 into an optional u32
+impl<'r, R: Row> FromRow<'r, R> for ColorJob
+where
+    &'r str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+    DbGuildId: Decode<'r, R::Database> + Type<R::Database>,
+    DbUserId: Decode<'r, R::Database> + Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        let kind: String = row.try_get("kind")?;
+
+        Ok(ColorJob {
+            id: row.try_get("id")?,
+            kind: JobKind::from_str(&kind).ok_or_else(|| sqlx::Error::Decode(format!("Unknown color job kind {}", kind).into()))?,
+            guid: row.try_get("guid")?,
+            uid: row.try_get("uid")?,
+            color: row.try_get::<Option<i64>, _>("color")?.map(|c| c as u32),
+            theme: row.try_get("theme")?,
+            duration: row.try_get("duration")?,
+            run_at: row.try_get("run_at")?,
+        })
+    }
+}
+
+/// Scheduled color changes - temporary colors that need reverting and guild wide themes
+pub struct ColorJobRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> ColorJobRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        ColorJobRepo { conn: conn.into() }
+    }
+
+    /// Schedules a member's color to go back to `color` at `run_at`.
+    /// When a revert is already pending its color is kept, so stacked temporary colors end on the color from before the first one,
+    /// and only the time is moved.
+    pub async fn schedule_revert(&mut self, uid: DbUserId, guid: DbGuildId, color: u32, run_at: u64) -> Result<(), Error> {
+        let pending = query_as::<(i64,)>(
+            r#"
+            SELECT id
+            FROM color_jobs
+            WHERE kind = $1 AND uid = $2 AND guid = $3;
+            "#,
+        )
+        .bind(JobKind::Revert)
+        .bind(uid)
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        match pending {
+            Some((id,)) => {
+                query(
+                    r#"
+                    UPDATE color_jobs
+                    SET run_at = $1
+                    WHERE id = $2;
+                    "#,
+                )
+                .bind(run_at as i64)
+                .bind(id)
+                .execute(&mut self.conn)
+                .await?;
+            }
+            None => {
+                query(
+                    r#"
+                    INSERT INTO color_jobs (kind, guid, uid, color, run_at)
+                    VALUES ($1, $2, $3, $4, $5);
+                    "#,
+                )
+                .bind(JobKind::Revert)
+                .bind(guid)
+                .bind(uid)
+                .bind(color)
+                .bind(run_at as i64)
+                .execute(&mut self.conn)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops a pending revert, eg when the member picked a new permanent color
+    pub async fn cancel_revert(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<(), Error> {
+        query(
+            r#"
+            DELETE FROM color_jobs
+            WHERE kind = $1 AND uid = $2 AND guid = $3;
+            "#,
+        )
+        .bind(JobKind::Revert)
+        .bind(uid)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the id of the new theme
+    pub async fn schedule_theme(&mut self, guid: DbGuildId, theme: String, duration: u64, run_at: u64) -> Result<i64, Error> {
+        let (id,) = query_as::<(i64,)>(
+            r#"
+            INSERT INTO color_jobs (kind, guid, theme, duration, run_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id;
+            "#,
+        )
+        .bind(JobKind::Theme)
+        .bind(guid)
+        .bind(theme)
+        .bind(duration as i64)
+        .bind(run_at as i64)
+        .fetch_one(&mut self.conn)
+        .await?;
+
+        Ok(id)
+    }
+
+    pub async fn get_guild_themes(&mut self, guid: DbGuildId) -> Result<Vec<ColorJob>, Error> {
+        let result = query_as::<ColorJob>(
+            r#"
+            SELECT *
+            FROM color_jobs
+            WHERE kind = $1 AND guid = $2
+            ORDER BY run_at;
+            "#,
+        )
+        .bind(JobKind::Theme)
+        .bind(guid)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Removes a scheduled theme, returns false when the guild has no theme with that id
+    pub async fn cancel_theme(&mut self, guid: DbGuildId, id: i64) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            DELETE FROM color_jobs
+            WHERE kind = $1 AND guid = $2 AND id = $3;
+            "#,
+        )
+        .bind(JobKind::Theme)
+        .bind(guid)
+        .bind(id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+
+    pub async fn get_due(&mut self) -> Result<Vec<ColorJob>, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        let result = query_as::<ColorJob>(
+            r#"
+            SELECT *
+            FROM color_jobs
+            WHERE run_at <= $1
+            ORDER BY run_at;
+            "#,
+        )
+        .bind(now as i64)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn delete(&mut self, id: i64) -> Result<(), Error> {
+        query(
+            r#"
+            DELETE FROM color_jobs
+            WHERE id = $1;
+            "#,
+        )
+        .bind(id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::color_repo::ColorRepo;
+    use crate::db_handlers::ids::DbRoleId;
+    use crate::db_handlers::for_each_backend;
+
+    const GUILD: DbGuildId = DbGuildId(10);
+    const USER: DbUserId = DbUserId(20);
+
+    #[tokio::test]
+    async fn pending_reverts_keep_their_original_color() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            ColorRepo::new(&mut conn).create(DbRoleId(30), USER, GUILD, 0xcccccc, "one".to_string()).await.unwrap();
+            let mut repo = ColorJobRepo::new(&mut conn);
+
+            repo.schedule_revert(USER, GUILD, 0xaaaaaa, 0).await.unwrap();
+            repo.schedule_revert(USER, GUILD, 0xbbbbbb, 0).await.unwrap();
+
+            let due = repo.get_due().await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].kind, JobKind::Revert);
+            assert_eq!(due[0].color, Some(0xaaaaaa));
+
+            repo.cancel_revert(USER, GUILD).await.unwrap();
+            assert!(repo.get_due().await.unwrap().is_empty());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn stacked_temporary_colors_revert_to_the_permanent_color() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            ColorRepo::new(&mut conn).create(DbRoleId(30), USER, GUILD, 0xaaaaaa, "one".to_string()).await.unwrap();
+
+            // Each temporary color schedules a revert to the color the member has at that moment
+            for (color, run_at) in [(0xbbbbbb, 100), (0xcccccc, 50)] {
+                let current = ColorRepo::new(&mut conn).get(USER, GUILD).await.unwrap().unwrap().color;
+                ColorJobRepo::new(&mut conn).schedule_revert(USER, GUILD, current, run_at).await.unwrap();
+                ColorRepo::new(&mut conn).update_color(DbRoleId(30), color).await.unwrap();
+            }
+
+            let due = ColorJobRepo::new(&mut conn).get_due().await.unwrap();
+            assert_eq!(due.len(), 1);
+            assert_eq!(due[0].color, Some(0xaaaaaa));
+            assert_eq!(due[0].run_at, 50);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn reverts_go_with_the_color_role() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+
+            assert!(ColorJobRepo::new(&mut conn).schedule_revert(USER, GUILD, 0, 0).await.is_err());
+
+            ColorRepo::new(&mut conn).create(DbRoleId(30), USER, GUILD, 0, "one".to_string()).await.unwrap();
+            ColorJobRepo::new(&mut conn).schedule_revert(USER, GUILD, 0, 0).await.unwrap();
+            ColorRepo::new(&mut conn).delete(DbRoleId(30)).await.unwrap();
+
+            assert!(ColorJobRepo::new(&mut conn).get_due().await.unwrap().is_empty());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn themes_can_only_be_cancelled_by_their_guild() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorJobRepo::new(&mut conn);
+
+            let id = repo.schedule_theme(GUILD, "ff0000,00ff00".to_string(), 60, 0).await.unwrap();
+
+            let themes = repo.get_guild_themes(GUILD).await.unwrap();
+            assert_eq!(themes.len(), 1);
+            assert_eq!(themes[0].kind, JobKind::Theme);
+
+            assert!(!repo.cancel_theme(DbGuildId(11), id).await.unwrap());
+            assert!(repo.cancel_theme(GUILD, id).await.unwrap());
+        }).await;
+    }
+}
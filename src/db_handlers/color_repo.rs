@@ -0,0 +1,714 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::Error;
+use super::ids::{DbGuildId, DbRoleId, DbUserId};
+use super::backend::{query, query_as, DbConn};
+use sqlx::{ColumnIndex, Decode, FromRow, Row, Type};
+use tracing::info;
+
+#[derive(sqlx::FromRow)]
+pub struct ColorRow {
+    pub role_id: DbRoleId,
+    pub uid: DbUserId,
+    pub guid: DbGuildId,
+    #[sqlx(try_from = "i64")]
+    pub color: u32,
+    pub role_name: String,
+}
+
+/// What caused a color change, stored with every entry of the color history
+#[derive(Clone, Copy)]
+pub enum ColorSource {
+    Set,
+    Steal,
+    Admin,
+    /// Temporary colors running out and guild wide themes
+    Scheduled,
+    /// A step of a color cycle, or going back to the color from before it
+    Cycle,
+}
+
+impl ColorSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorSource::Set => "set",
+            ColorSource::Steal => "steal",
+            ColorSource::Admin => "admin",
+            ColorSource::Scheduled => "scheduled",
+            ColorSource::Cycle => "cycle",
+        }
+    }
+}
+
+/// A single color change - old_color is empty for the change that created the color role
+pub struct ColorHistory {
+    pub id: i64,
+    pub old_color: Option<u32>,
+    pub color: u32,
+    pub source: String,
+    pub timestamp: i64,
+}
+
+// By hand as the derive can't turn an optional i64 into an optional u32
+impl<'r, R: Row> FromRow<'r, R> for ColorHistory
+where
+    &'r str: ColumnIndex<R>,
+    i64: Decode<'r, R::Database> + Type<R::Database>,
+    String: Decode<'r, R::Database> + Type<R::Database>,
+{
+    fn from_row(row: &'r R) -> Result<Self, sqlx::Error> {
+        Ok(ColorHistory {
+            id: row.try_get("id")?,
+            old_color: row.try_get::<Option<i64>, _>("old_color")?.map(|c| c as u32),
+            color: row.try_get::<i64, _>("color")? as u32,
+            source: row.try_get("source")?,
+            timestamp: row.try_get("timestamp")?,
+        })
+    }
+}
+
+/// How a member wants their color to be protected from `/color steal`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum StealProtection {
+    Off,
+    Ask,
+    On,
+}
+
+impl StealProtection {
+    fn from_i64(value: i64) -> Self {
+        match value {
+            1 => StealProtection::Ask,
+            2 => StealProtection::On,
+            _ => StealProtection::Off,
+        }
+    }
+
+    fn as_i64(&self) -> i64 {
+        match self {
+            StealProtection::Off => 0,
+            StealProtection::Ask => 1,
+            StealProtection::On => 2,
+        }
+    }
+}
+
+/// Per member color preferences
+pub struct ColorMember {
+    pub steal_protection: StealProtection,
+    /// Unix timestamp of the last time this member stole a color
+    pub last_steal: Option<u64>,
+}
+
+/// The kind of eligibility rule, stored in the color_rules table
+#[derive(Clone, Copy)]
+enum RuleKind {
+    /// Members need at least one of the required roles
+    RequiredRole,
+    /// Users on the deny list can never have a color
+    DeniedUser,
+}
+
+impl RuleKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RuleKind::RequiredRole => "required_role",
+            RuleKind::DeniedUser => "denied_user",
+        }
+    }
+}
+
+/// The color roles and everything kept per member around them - history, steal preferences and eligibility rules.
+/// Works on any connection, a pooled one or an open transaction.
+pub struct ColorRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> ColorRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        ColorRepo { conn: conn.into() }
+    }
+
+    pub async fn create(&mut self, role_id: DbRoleId, uid: DbUserId, guid: DbGuildId, color: u32, name: String) -> Result<(), Error> {
+        let result = query(
+            r#"
+            INSERT INTO colors (
+                role_id, uid, guid, color, role_name
+            ) VALUES ($1, $2, $3, $4, $5);
+            "#,
+        )
+        .bind(role_id)
+        .bind(uid)
+        .bind(guid)
+        .bind(color)
+        .bind(name)
+        .execute(&mut self.conn)
+        .await?;
+
+        match result.rows_affected() {
+            0 => info!("Color did not insert?"),
+            _ => info!("Inserted new color into Database"),
+        }
+
+        Ok(())
+    }
+
+    pub async fn get(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<Option<ColorRow>, Error> {
+        let result = query_as::<ColorRow>(
+            r#"
+            SELECT *
+            FROM colors
+            WHERE uid = $1 AND guid = $2;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_by_role(&mut self, role_id: DbRoleId) -> Result<Option<ColorRow>, Error> {
+        let result = query_as::<ColorRow>(
+            r#"
+            SELECT *
+            FROM colors
+            WHERE role_id = $1;
+            "#,
+        )
+        .bind(role_id)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn get_guild(&mut self, guid: DbGuildId) -> Result<Vec<ColorRow>, Error> {
+        let result = query_as::<ColorRow>(
+            r#"
+            SELECT *
+            FROM colors
+            WHERE guid = $1;
+            "#,
+        )
+        .bind(guid)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// The color roles of a user across every guild
+    pub async fn get_user(&mut self, uid: DbUserId) -> Result<Vec<ColorRow>, Error> {
+        let result = query_as::<ColorRow>(
+            r#"
+            SELECT *
+            FROM colors
+            WHERE uid = $1;
+            "#,
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    /// Also clears any pending color of the role
+    pub async fn update_color(&mut self, role_id: DbRoleId, color: u32) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE colors
+            SET color = $1, pending_color = NULL
+            WHERE role_id = $2;
+            "#,
+        )
+        .bind(color)
+        .bind(role_id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Marks the color the bot is about to give the role, so its role update event is not taken for a change made in the server settings.
+    /// `update_color` clears it once discord accepted the change, None clears it when discord did not.
+    pub async fn set_pending_color(&mut self, role_id: DbRoleId, color: Option<u32>) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE colors
+            SET pending_color = $1
+            WHERE role_id = $2;
+            "#,
+        )
+        .bind(color)
+        .bind(role_id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Drops the pending color of a member's role after the change failed, the role might have been replaced along the way
+    pub async fn clear_pending_color(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE colors
+            SET pending_color = NULL
+            WHERE uid = $1 AND guid = $2;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// True when the bot is giving the role this color right now
+    pub async fn is_pending_color(&mut self, role_id: DbRoleId, color: u32) -> Result<bool, Error> {
+        let result = query_as::<(i64,)>(
+            r#"
+            SELECT role_id
+            FROM colors
+            WHERE role_id = $1 AND pending_color = $2;
+            "#,
+        )
+        .bind(role_id)
+        .bind(color)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result.is_some())
+    }
+
+    /// Only changes the color when it is still the expected one, returns false when it was changed in the meantime
+    pub async fn swap_color(&mut self, role_id: DbRoleId, expected: u32, color: u32) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            UPDATE colors
+            SET color = $1
+            WHERE role_id = $2 AND color = $3;
+            "#,
+        )
+        .bind(color)
+        .bind(role_id)
+        .bind(expected)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+
+    pub async fn update_role_id(&mut self, old_role_id: DbRoleId, new_role_id: DbRoleId) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE colors
+            SET role_id = $1
+            WHERE role_id = $2;
+            "#,
+        )
+        .bind(new_role_id)
+        .bind(old_role_id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_name(&mut self, role_id: DbRoleId, name: String) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE colors
+            SET role_name = $1
+            WHERE role_id = $2;
+            "#,
+        )
+        .bind(name)
+        .bind(role_id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&mut self, role_id: DbRoleId) -> Result<(), Error> {
+        query(
+            r#"
+            DELETE FROM colors
+            WHERE role_id = $1;
+            "#,
+        )
+        .bind(role_id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remembers a color role that got replaced, in case it still exists in the guild
+    pub async fn abandon_role(&mut self, role_id: DbRoleId, guid: DbGuildId) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO abandoned_roles (role_id, guid)
+            VALUES ($1, $2)
+            ON CONFLICT (role_id) DO NOTHING;
+            "#,
+        )
+        .bind(role_id)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_abandoned_roles(&mut self, guid: DbGuildId) -> Result<Vec<DbRoleId>, Error> {
+        let result = query_as::<(DbRoleId,)>(
+            r#"
+            SELECT role_id
+            FROM abandoned_roles
+            WHERE guid = $1;
+            "#,
+        )
+        .bind(guid)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result.into_iter().map(|(role_id,)| role_id).collect())
+    }
+
+    /// The role got deleted, or turned out to be gone already
+    pub async fn forget_abandoned_role(&mut self, role_id: DbRoleId) -> Result<(), Error> {
+        query(
+            r#"
+            DELETE FROM abandoned_roles
+            WHERE role_id = $1;
+            "#,
+        )
+        .bind(role_id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn add_history(
+        &mut self,
+        uid: DbUserId,
+        guid: DbGuildId,
+        old_color: Option<u32>,
+        color: u32,
+        source: ColorSource,
+    ) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        query(
+            r#"
+            INSERT INTO color_history (uid, guid, old_color, color, source, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6);
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .bind(old_color)
+        .bind(color)
+        .bind(source.as_str())
+        .bind(now as i64)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The most recent color changes of a user, newest first
+    pub async fn get_history(&mut self, uid: DbUserId, guid: DbGuildId, limit: u32) -> Result<Vec<ColorHistory>, Error> {
+        let result = query_as::<ColorHistory>(
+            r#"
+            SELECT id, old_color, color, source, timestamp
+            FROM color_history
+            WHERE uid = $1 AND guid = $2
+            ORDER BY id DESC
+            LIMIT $3;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .bind(limit)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn delete_history_entry(&mut self, id: i64) -> Result<(), Error> {
+        query(
+            r#"
+            DELETE FROM color_history
+            WHERE id = $1;
+            "#,
+        )
+        .bind(id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_member(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<Option<ColorMember>, Error> {
+        let result = query_as::<(i64, Option<i64>)>(
+            r#"
+            SELECT steal_protection, last_steal
+            FROM color_members
+            WHERE uid = $1 AND guid = $2;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?
+        .map(|(protection, last_steal)| ColorMember {
+            steal_protection: StealProtection::from_i64(protection),
+            last_steal: last_steal.map(|t| t as u64),
+        });
+
+        Ok(result)
+    }
+
+    pub async fn set_steal_protection(&mut self, uid: DbUserId, guid: DbGuildId, protection: StealProtection) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_members (uid, guid, steal_protection)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (uid, guid) DO UPDATE
+            SET steal_protection = excluded.steal_protection;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .bind(protection.as_i64())
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_last_steal(&mut self, uid: DbUserId, guid: DbGuildId) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        query(
+            r#"
+            INSERT INTO color_members (uid, guid, last_steal)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (uid, guid) DO UPDATE
+            SET last_steal = excluded.last_steal;
+            "#,
+        )
+        .bind(uid)
+        .bind(guid)
+        .bind(now as i64)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Roles of which members need at least one to have a color, empty when anyone can
+    pub async fn required_roles(&mut self, guid: DbGuildId) -> Result<Vec<DbRoleId>, Error> {
+        let targets = self.get_rules(guid, RuleKind::RequiredRole).await?;
+        Ok(targets.into_iter().map(DbRoleId).collect())
+    }
+
+    pub async fn denied_users(&mut self, guid: DbGuildId) -> Result<Vec<DbUserId>, Error> {
+        let targets = self.get_rules(guid, RuleKind::DeniedUser).await?;
+        Ok(targets.into_iter().map(DbUserId).collect())
+    }
+
+    /// Adds or removes a required role, returns false when nothing changed
+    pub async fn set_required_role(&mut self, guid: DbGuildId, role_id: DbRoleId, required: bool) -> Result<bool, Error> {
+        self.set_rule(guid, RuleKind::RequiredRole, role_id.get(), required).await
+    }
+
+    /// Adds or removes a user from the deny list, returns false when nothing changed
+    pub async fn set_denied_user(&mut self, guid: DbGuildId, uid: DbUserId, denied: bool) -> Result<bool, Error> {
+        self.set_rule(guid, RuleKind::DeniedUser, uid.get(), denied).await
+    }
+
+    async fn get_rules(&mut self, guid: DbGuildId, kind: RuleKind) -> Result<Vec<u64>, Error> {
+        let result = query_as::<(i64,)>(
+            r#"
+            SELECT target
+            FROM color_rules
+            WHERE guild_id = $1 AND kind = $2;
+            "#,
+        )
+        .bind(guid)
+        .bind(kind.as_str())
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(target,)| target as u64)
+        .collect();
+
+        Ok(result)
+    }
+
+    async fn set_rule(&mut self, guid: DbGuildId, kind: RuleKind, target: u64, enabled: bool) -> Result<bool, Error> {
+        let sql = match enabled {
+            true => "INSERT INTO color_rules (guild_id, kind, target) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING;",
+            false => "DELETE FROM color_rules WHERE guild_id = $1 AND kind = $2 AND target = $3;",
+        };
+
+        let result = query(sql)
+            .bind(guid)
+            .bind(kind.as_str())
+            .bind(target as i64)
+            .execute(&mut self.conn)
+            .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::for_each_backend;
+
+    const GUILD: DbGuildId = DbGuildId(10);
+    const USER: DbUserId = DbUserId(20);
+    const ROLE: DbRoleId = DbRoleId(30);
+
+    #[tokio::test]
+    async fn color_rows_round_trip() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorRepo::new(&mut conn);
+
+            repo.create(ROLE, USER, GUILD, 0xff0000, "red".to_string()).await.unwrap();
+            repo.update_color(ROLE, 0x00ff00).await.unwrap();
+            repo.update_role_id(ROLE, DbRoleId(31)).await.unwrap();
+
+            let row = repo.get(USER, GUILD).await.unwrap().unwrap();
+            assert_eq!(row.role_id, DbRoleId(31));
+            assert_eq!(row.color, 0x00ff00);
+            assert!(repo.get_by_role(ROLE).await.unwrap().is_none());
+
+            repo.delete(DbRoleId(31)).await.unwrap();
+            assert!(repo.get_guild(GUILD).await.unwrap().is_empty());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn pending_colors_clear_once_applied() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorRepo::new(&mut conn);
+
+            repo.create(ROLE, USER, GUILD, 1, "one".to_string()).await.unwrap();
+            repo.set_pending_color(ROLE, Some(2)).await.unwrap();
+
+            assert!(repo.is_pending_color(ROLE, 2).await.unwrap());
+            assert!(!repo.is_pending_color(ROLE, 3).await.unwrap());
+
+            repo.update_color(ROLE, 2).await.unwrap();
+            assert!(!repo.is_pending_color(ROLE, 2).await.unwrap());
+
+            repo.set_pending_color(ROLE, Some(3)).await.unwrap();
+            repo.set_pending_color(ROLE, None).await.unwrap();
+            assert!(!repo.is_pending_color(ROLE, 3).await.unwrap());
+
+            // Replaced along the way, the member's role is cleared whatever its id is now
+            repo.set_pending_color(ROLE, Some(4)).await.unwrap();
+            repo.update_role_id(ROLE, DbRoleId(ROLE.0 + 1)).await.unwrap();
+            repo.clear_pending_color(USER, GUILD).await.unwrap();
+            assert!(!repo.is_pending_color(DbRoleId(ROLE.0 + 1), 4).await.unwrap());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn swapping_needs_the_expected_color() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorRepo::new(&mut conn);
+
+            repo.create(ROLE, USER, GUILD, 1, "one".to_string()).await.unwrap();
+
+            assert!(!repo.swap_color(ROLE, 2, 3).await.unwrap());
+            assert!(repo.swap_color(ROLE, 1, 3).await.unwrap());
+            assert_eq!(repo.get(USER, GUILD).await.unwrap().unwrap().color, 3);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn history_is_newest_first() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorRepo::new(&mut conn);
+
+            repo.add_history(USER, GUILD, None, 1, ColorSource::Set).await.unwrap();
+            repo.add_history(USER, GUILD, Some(1), 2, ColorSource::Steal).await.unwrap();
+
+            let history = repo.get_history(USER, GUILD, 10).await.unwrap();
+            assert_eq!(history.iter().map(|h| h.color).collect::<Vec<_>>(), vec![2, 1]);
+            assert_eq!(history[0].source, "steal");
+
+            repo.delete_history_entry(history[0].id).await.unwrap();
+            assert_eq!(repo.get_history(USER, GUILD, 10).await.unwrap().len(), 1);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn member_preferences_keep_each_other() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorRepo::new(&mut conn);
+
+            assert!(repo.get_member(USER, GUILD).await.unwrap().is_none());
+
+            repo.set_steal_protection(USER, GUILD, StealProtection::Ask).await.unwrap();
+            repo.set_last_steal(USER, GUILD).await.unwrap();
+
+            let member = repo.get_member(USER, GUILD).await.unwrap().unwrap();
+            assert_eq!(member.steal_protection, StealProtection::Ask);
+            assert!(member.last_steal.is_some());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn rules_only_report_actual_changes() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ColorRepo::new(&mut conn);
+
+            assert!(repo.set_required_role(GUILD, ROLE, true).await.unwrap());
+            assert!(!repo.set_required_role(GUILD, ROLE, true).await.unwrap());
+            assert!(repo.set_denied_user(GUILD, USER, true).await.unwrap());
+
+            assert_eq!(repo.required_roles(GUILD).await.unwrap(), vec![ROLE]);
+            assert_eq!(repo.denied_users(GUILD).await.unwrap(), vec![USER]);
+
+            assert!(repo.set_denied_user(GUILD, USER, false).await.unwrap());
+            assert!(repo.denied_users(GUILD).await.unwrap().is_empty());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn rolled_back_transactions_leave_nothing_behind() {
+        for_each_backend(async |pool| {
+            let mut tx = pool.begin().await.unwrap();
+            ColorRepo::new(&mut tx).create(ROLE, USER, GUILD, 1, "one".to_string()).await.unwrap();
+            tx.rollback().await.unwrap();
+
+            assert!(ColorRepo::new(&mut pool.acquire().await.unwrap()).get(USER, GUILD).await.unwrap().is_none());
+
+            let mut tx = pool.begin().await.unwrap();
+            ColorRepo::new(&mut tx).create(ROLE, USER, GUILD, 1, "one".to_string()).await.unwrap();
+            tx.commit().await.unwrap();
+
+            assert!(ColorRepo::new(&mut pool.acquire().await.unwrap()).get(USER, GUILD).await.unwrap().is_some());
+        }).await;
+    }
+}
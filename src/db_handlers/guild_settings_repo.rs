@@ -0,0 +1,413 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::Error;
+use super::ids::{DbChannelId, DbGuildId, DbRoleId};
+use super::backend::{query, query_as, DbConn};
+
+/// Starboard settings per guild
+#[derive(sqlx::FromRow)]
+pub struct GuildSettings {
+    pub guild_id: DbGuildId,
+    pub starboard_enabled: bool,
+    pub starboard_channel: Option<DbChannelId>,
+    #[sqlx(try_from = "i64")]
+    pub starboard_min: u8,
+}
+
+/// Per guild settings for the color roles
+#[derive(sqlx::FromRow)]
+pub struct ColorSettings {
+    pub anchor_role: Option<DbRoleId>,
+    pub remove_on_leave: bool,
+    pub steal_enabled: bool,
+    /// Seconds a member has to wait between steals
+    #[sqlx(try_from = "i64")]
+    pub steal_cooldown: u32,
+    /// Template for the color role names, None uses the default
+    pub naming_template: Option<String>,
+    /// Days a member has to be in the guild before they can get a color
+    #[sqlx(try_from = "i64")]
+    pub min_member_age: u32,
+    pub booster_only: bool,
+    /// Channel every color change gets posted in
+    pub log_channel: Option<DbChannelId>,
+}
+
+/// Settings kept per guild - the prefix, the starboard ones and the color role ones - and which guilds removed the bot
+pub struct GuildSettingsRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> GuildSettingsRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        GuildSettingsRepo { conn: conn.into() }
+    }
+
+    pub async fn get(&mut self, guid: DbGuildId) -> Result<Option<GuildSettings>, Error> {
+        let result = query_as::<GuildSettings>(
+            r#"
+            SELECT *
+            FROM guild_settings
+            WHERE guild_id = $1
+            "#,
+        )
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn update(&mut self, new: GuildSettings) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO guild_settings(guild_id, starboard_enabled, starboard_channel, starboard_min)
+            VALUES($1, $2, $3, $4)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET starboard_enabled = excluded.starboard_enabled, starboard_channel = excluded.starboard_channel, starboard_min = excluded.starboard_min;
+            "#
+        )
+        .bind(new.guild_id)
+        .bind(new.starboard_enabled)
+        .bind(new.starboard_channel)
+        .bind(new.starboard_min)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn toggle_starboard(&mut self, guid: DbGuildId, enabled: bool) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE guild_settings
+            SET starboard_enabled = $1
+            WHERE guild_id = $2;
+            "#
+        )
+        .bind(enabled)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns false if the guild has no starboard channel yet
+    pub async fn set_required_stars(&mut self, guid: DbGuildId, new_stars: u8) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            UPDATE guild_settings
+            SET starboard_min = $1
+            WHERE guild_id = $2 AND starboard_channel IS NOT NULL;
+            "#
+        )
+        .bind(new_stars)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+
+    pub async fn get_color_settings(&mut self, guid: DbGuildId) -> Result<Option<ColorSettings>, Error> {
+        let result = query_as::<ColorSettings>(
+            r#"
+            SELECT anchor_role, remove_on_leave, steal_enabled, steal_cooldown, naming_template, min_member_age, booster_only, log_channel
+            FROM color_settings
+            WHERE guild_id = $1;
+            "#,
+        )
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn set_anchor_role(&mut self, guid: DbGuildId, anchor_role: Option<DbRoleId>) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_settings (guild_id, anchor_role)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET anchor_role = excluded.anchor_role;
+            "#,
+        )
+        .bind(guid)
+        .bind(anchor_role)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_log_channel(&mut self, guid: DbGuildId, log_channel: Option<DbChannelId>) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_settings (guild_id, log_channel)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET log_channel = excluded.log_channel;
+            "#,
+        )
+        .bind(guid)
+        .bind(log_channel)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_remove_on_leave(&mut self, guid: DbGuildId, remove_on_leave: bool) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_settings (guild_id, remove_on_leave)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET remove_on_leave = excluded.remove_on_leave;
+            "#,
+        )
+        .bind(guid)
+        .bind(remove_on_leave)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_steal_settings(&mut self, guid: DbGuildId, enabled: bool, cooldown: u32) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_settings (guild_id, steal_enabled, steal_cooldown)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET steal_enabled = excluded.steal_enabled, steal_cooldown = excluded.steal_cooldown;
+            "#,
+        )
+        .bind(guid)
+        .bind(enabled)
+        .bind(cooldown)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_naming_template(&mut self, guid: DbGuildId, template: Option<String>) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_settings (guild_id, naming_template)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET naming_template = excluded.naming_template;
+            "#,
+        )
+        .bind(guid)
+        .bind(template)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_eligibility(&mut self, guid: DbGuildId, min_member_age: u32, booster_only: bool) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO color_settings (guild_id, min_member_age, booster_only)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET min_member_age = excluded.min_member_age, booster_only = excluded.booster_only;
+            "#,
+        )
+        .bind(guid)
+        .bind(min_member_age)
+        .bind(booster_only)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The text command prefix the guild picked, None when it uses the configured one
+    pub async fn get_prefix(&mut self, guid: DbGuildId) -> Result<Option<String>, Error> {
+        let result = query_as::<(Option<String>,)>(
+            r#"
+            SELECT prefix
+            FROM guild_settings
+            WHERE guild_id = $1;
+            "#
+        )
+        .bind(guid)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result.and_then(|(prefix,)| prefix))
+    }
+
+    /// None goes back to the configured prefix, `default_stars` is only used when the guild has no settings yet
+    pub async fn set_prefix(&mut self, guid: DbGuildId, prefix: Option<String>, default_stars: u8) -> Result<(), Error> {
+        // A guild without starboard settings gets a disabled starboard without a channel, which counts as not set up
+        query(
+            r#"
+            INSERT INTO guild_settings (guild_id, starboard_enabled, starboard_min, prefix)
+            VALUES ($1, FALSE, $2, $3)
+            ON CONFLICT (guild_id) DO UPDATE
+            SET prefix = excluded.prefix;
+            "#
+        )
+        .bind(guid)
+        .bind(default_stars)
+        .bind(prefix)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remembers when the bot was removed from a guild, a guild that left before keeps its first time
+    pub async fn mark_left(&mut self, guid: DbGuildId) -> Result<(), Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        query(
+            r#"
+            INSERT INTO left_guilds (guild_id, left_at)
+            VALUES ($1, $2)
+            ON CONFLICT (guild_id) DO NOTHING;
+            "#
+        )
+        .bind(guid)
+        .bind(now as i64)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Forgets that the bot left a guild, returns false when it never did
+    pub async fn mark_returned(&mut self, guid: DbGuildId) -> Result<bool, Error> {
+        let result = query(
+            r#"
+            DELETE FROM left_guilds
+            WHERE guild_id = $1;
+            "#
+        )
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(result.rows_affected() != 0)
+    }
+
+    /// Guilds the bot was removed from before `cutoff`
+    pub async fn left_before(&mut self, cutoff: u64) -> Result<Vec<DbGuildId>, Error> {
+        let result = query_as::<(DbGuildId,)>(
+            r#"
+            SELECT guild_id
+            FROM left_guilds
+            WHERE left_at < $1;
+            "#
+        )
+        .bind(cutoff as i64)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guid,)| guid)
+        .collect();
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::for_each_backend;
+
+    const GUILD: DbGuildId = DbGuildId(10);
+
+    #[tokio::test]
+    async fn starboard_settings_need_a_setup_first() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = GuildSettingsRepo::new(&mut conn);
+
+            assert!(!repo.set_required_stars(GUILD, 5).await.unwrap());
+
+            let settings = GuildSettings {
+                guild_id: GUILD,
+                starboard_enabled: false,
+                starboard_channel: Some(DbChannelId(99)),
+                starboard_min: 3,
+            };
+            repo.update(settings).await.unwrap();
+            repo.toggle_starboard(GUILD, true).await.unwrap();
+            assert!(repo.set_required_stars(GUILD, 5).await.unwrap());
+
+            let settings = repo.get(GUILD).await.unwrap().unwrap();
+            assert!(settings.starboard_enabled);
+            assert_eq!(settings.starboard_channel, Some(DbChannelId(99)));
+            assert_eq!(settings.starboard_min, 5);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn color_settings_fill_in_defaults() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = GuildSettingsRepo::new(&mut conn);
+
+            assert!(repo.get_color_settings(GUILD).await.unwrap().is_none());
+
+            repo.set_anchor_role(GUILD, Some(DbRoleId(7))).await.unwrap();
+            repo.set_eligibility(GUILD, 3, true).await.unwrap();
+
+            let settings = repo.get_color_settings(GUILD).await.unwrap().unwrap();
+            assert_eq!(settings.anchor_role, Some(DbRoleId(7)));
+            assert_eq!(settings.min_member_age, 3);
+            assert!(settings.booster_only);
+            assert!(settings.steal_enabled);
+            assert_eq!(settings.log_channel, None);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn leaving_is_forgotten_on_return() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = GuildSettingsRepo::new(&mut conn);
+
+            repo.mark_left(GUILD).await.unwrap();
+            repo.mark_left(GUILD).await.unwrap();
+            assert!(repo.left_before(0).await.unwrap().is_empty());
+            assert_eq!(repo.left_before(u32::MAX as u64 * 4).await.unwrap(), vec![GUILD]);
+
+            assert!(repo.mark_returned(GUILD).await.unwrap());
+            assert!(!repo.mark_returned(GUILD).await.unwrap());
+            assert!(repo.left_before(u32::MAX as u64 * 4).await.unwrap().is_empty());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn prefixes_leave_the_starboard_alone() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = GuildSettingsRepo::new(&mut conn);
+
+            assert_eq!(repo.get_prefix(GUILD).await.unwrap(), None);
+            repo.set_prefix(GUILD, Some("!".to_string()), 7).await.unwrap();
+            assert_eq!(repo.get_prefix(GUILD).await.unwrap().as_deref(), Some("!"));
+            let settings = repo.get(GUILD).await.unwrap().unwrap();
+            assert!(!settings.starboard_enabled);
+            assert_eq!(settings.starboard_min, 7);
+            assert!(!repo.set_required_stars(GUILD, 5).await.unwrap());
+
+            repo.toggle_starboard(GUILD, true).await.unwrap();
+            repo.set_prefix(GUILD, None, 3).await.unwrap();
+            assert_eq!(repo.get_prefix(GUILD).await.unwrap(), None);
+            let settings = repo.get(GUILD).await.unwrap().unwrap();
+            assert!(settings.starboard_enabled);
+            assert_eq!(settings.starboard_min, 7);
+        }).await;
+    }
+}
@@ -0,0 +1,144 @@
+//! Discord snowflakes as they are stored in the database.
+//! SQLite and Postgres only know signed 64 bit integers, the ids are stored as their bit pattern and read back the same way.
+
+use std::fmt;
+
+use poise::serenity_prelude as serenity;
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgTypeInfo, PgValueRef};
+use sqlx::sqlite::{SqliteArgumentValue, SqliteTypeInfo, SqliteValueRef};
+use sqlx::{Decode, Encode, Postgres, Sqlite, Type};
+
+use super::backend::{Bind, Value};
+
+macro_rules! snowflake_id {
+    ($(#[$meta:meta])* $name:ident => $serenity:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(pub u64);
+
+        impl $name {
+            pub fn get(self) -> u64 {
+                self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl From<serenity::$serenity> for $name {
+            fn from(id: serenity::$serenity) -> Self {
+                $name(id.get())
+            }
+        }
+
+        impl From<$name> for serenity::$serenity {
+            fn from(id: $name) -> Self {
+                serenity::$serenity::new(id.0)
+            }
+        }
+
+        impl PartialEq<serenity::$serenity> for $name {
+            fn eq(&self, other: &serenity::$serenity) -> bool {
+                self.0 == other.get()
+            }
+        }
+
+        impl Type<Sqlite> for $name {
+            fn type_info() -> SqliteTypeInfo {
+                <i64 as Type<Sqlite>>::type_info()
+            }
+
+            fn compatible(ty: &SqliteTypeInfo) -> bool {
+                <i64 as Type<Sqlite>>::compatible(ty)
+            }
+        }
+
+        impl<'q> Encode<'q, Sqlite> for $name {
+            fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'q>>) -> IsNull {
+                <i64 as Encode<Sqlite>>::encode_by_ref(&(self.0 as i64), buf)
+            }
+        }
+
+        impl<'r> Decode<'r, Sqlite> for $name {
+            fn decode(value: SqliteValueRef<'r>) -> Result<Self, BoxDynError> {
+                Ok($name(<i64 as Decode<Sqlite>>::decode(value)? as u64))
+            }
+        }
+
+        impl Type<Postgres> for $name {
+            fn type_info() -> PgTypeInfo {
+                <i64 as Type<Postgres>>::type_info()
+            }
+
+            fn compatible(ty: &PgTypeInfo) -> bool {
+                <i64 as Type<Postgres>>::compatible(ty)
+            }
+        }
+
+        impl Encode<'_, Postgres> for $name {
+            fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> IsNull {
+                <i64 as Encode<Postgres>>::encode_by_ref(&(self.0 as i64), buf)
+            }
+        }
+
+        impl<'r> Decode<'r, Postgres> for $name {
+            fn decode(value: PgValueRef<'r>) -> Result<Self, BoxDynError> {
+                Ok($name(<i64 as Decode<Postgres>>::decode(value)? as u64))
+            }
+        }
+
+        impl Bind for $name {
+            fn value(self) -> Value {
+                Value::Int(Some(self.0 as i64))
+            }
+
+            fn null() -> Value {
+                Value::Int(None)
+            }
+        }
+    };
+}
+
+snowflake_id!(DbUserId => UserId);
+snowflake_id!(DbGuildId => GuildId);
+snowflake_id!(DbRoleId => RoleId);
+snowflake_id!(DbChannelId => ChannelId);
+snowflake_id!(DbMessageId => MessageId);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::backend::{query_as, DbConn};
+    use crate::db_handlers::for_each_backend;
+
+    #[tokio::test]
+    async fn ids_above_i64_max_survive_a_round_trip() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+
+            let id = DbUserId(u64::MAX - 5);
+            let (back,) = query_as::<(DbUserId,)>("SELECT $1;")
+                .bind(id)
+                .fetch_one(&mut DbConn::from(&mut conn))
+                .await
+                .unwrap();
+
+            assert_eq!(back, id);
+        }).await;
+    }
+
+    #[test]
+    fn converts_to_and_from_serenity_ids() {
+        let role = serenity::RoleId::new(1234);
+        let id = DbRoleId::from(role);
+
+        assert_eq!(id, role);
+        assert_eq!(serenity::RoleId::from(id), role);
+        assert_eq!(id.to_string(), "1234");
+    }
+}
@@ -0,0 +1,454 @@
+//! Backups and upkeep of the SQLite file, and the retention job that drops old rows.
+//! Copies are made with SQLite's online backup API, so the bot keeps working while a backup runs.
+//! Postgres is left to its own tooling like pg_dump, only vacuuming works on both.
+
+use std::ffi::{c_int, CStr, CString};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libsqlite3_sys as ffi;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{ConnectOptions, SqliteConnection};
+use tracing::info;
+
+use super::super::Error;
+use super::backend::{query_as, Backend, DbConn, DbPool};
+use super::guild_settings_repo::GuildSettingsRepo;
+use super::privacy_repo::{PrivacyRepo, PurgedRows};
+
+const BACKUP_PREFIX: &str = "cyberbun-";
+const BACKUP_SUFFIX: &str = ".db";
+
+/// Where backups go and how many are kept, the `[backups]` section of the config
+#[derive(Clone, Debug)]
+pub struct BackupSettings {
+    pub dir: PathBuf,
+    /// Time between automatic backups, None turns them off
+    pub interval: Option<Duration>,
+    pub keep: usize,
+}
+
+impl Default for BackupSettings {
+    fn default() -> Self {
+        BackupSettings {
+            dir: "backups".into(),
+            interval: Some(Duration::from_secs(24 * 60 * 60)),
+            keep: 7,
+        }
+    }
+}
+
+/// How long rows are kept before the retention job drops them, the `[retention]` section of the config
+#[derive(Clone, Debug)]
+pub struct RetentionSettings {
+    /// None keeps everything
+    pub keep_for: Option<Duration>,
+    /// Time a guild that removed the bot has to add it back before its data is purged
+    pub guild_grace: Duration,
+}
+
+impl Default for RetentionSettings {
+    fn default() -> Self {
+        RetentionSettings {
+            keep_for: Some(Duration::from_secs(90 * 24 * 60 * 60)),
+            guild_grace: Duration::from_secs(30 * 24 * 60 * 60),
+        }
+    }
+}
+
+/// Purges completed reminders, old color history and unused member preferences past the retention period,
+/// and everything of the guilds that removed the bot longer than the grace period ago
+pub async fn run_retention(pool: &DbPool, settings: &RetentionSettings) -> Result<PurgedRows, Error> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+    let mut purged = match settings.keep_for {
+        Some(keep_for) => PrivacyRepo::new(&mut pool.acquire().await?).purge_before(now.saturating_sub(keep_for.as_secs())).await?,
+        None => PurgedRows::default(),
+    };
+
+    let left = GuildSettingsRepo::new(&mut pool.acquire().await?)
+        .left_before(now.saturating_sub(settings.guild_grace.as_secs()))
+        .await?;
+
+    for guid in left {
+        // One guild at a time, a guild is either gone completely or not at all
+        let mut tx = pool.begin().await?;
+        purged.left_guilds += PrivacyRepo::new(&mut tx).delete_guild(guid).await?;
+        tx.commit().await?;
+
+        info!("Purged the data of guild {} after its grace period", guid);
+    }
+
+    if purged.total() != 0 {
+        info!("Retention purged {:?}", purged);
+    }
+
+    Ok(purged)
+}
+
+/// Writes a timestamped copy of the database to the backup directory and returns its path
+pub async fn backup_now(pool: &DbPool, settings: &BackupSettings) -> Result<PathBuf, Error> {
+    let Some(pool) = pool.sqlite() else {
+        return Err("Backups are only made of SQLite databases, use pg_dump for Postgres".into());
+    };
+
+    tokio::fs::create_dir_all(&settings.dir).await?;
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+    // Backups made within the same second get a counter, creating the file first claims the name
+    let mut count = 0;
+    let path = loop {
+        let name = match count {
+            0 => format!("{}{}{}", BACKUP_PREFIX, now, BACKUP_SUFFIX),
+            _ => format!("{}{}-{}{}", BACKUP_PREFIX, now, count, BACKUP_SUFFIX),
+        };
+        let path = settings.dir.join(name);
+
+        match tokio::fs::OpenOptions::new().write(true).create_new(true).open(&path).await {
+            Ok(_) => break path,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => count += 1,
+            Err(err) => return Err(err.into()),
+        }
+    };
+
+    let mut conn = pool.acquire().await?;
+    copy_database(&mut conn, &path).await?;
+
+    info!("Database backed up to {}", path.display());
+
+    Ok(path)
+}
+
+/// Makes a backup when the newest one is older than the backup interval, then prunes the old ones.
+/// Returns the path of the new backup, if one was made.
+pub async fn run_scheduled_backup(pool: &DbPool, settings: &BackupSettings) -> Result<Option<PathBuf>, Error> {
+    let (Some(interval), Backend::Sqlite) = (settings.interval, pool.backend()) else {
+        return Ok(None);
+    };
+
+    tokio::fs::create_dir_all(&settings.dir).await?;
+
+    // Going by the newest file instead of a timer keeps restarts from skipping or doubling backups
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+    let newest = list_backups(&settings.dir).await?.first().map(|(stamp, _)| *stamp);
+    if newest.is_some_and(|stamp| now.saturating_sub(stamp) < interval.as_secs()) {
+        return Ok(None);
+    }
+
+    let path = backup_now(pool, settings).await?;
+    prune_backups(settings).await?;
+
+    Ok(Some(path))
+}
+
+/// Deletes the oldest backups so only the newest `settings.keep` remain, returns how many were deleted
+pub async fn prune_backups(settings: &BackupSettings) -> Result<usize, Error> {
+    let backups = list_backups(&settings.dir).await?;
+
+    let mut removed = 0;
+    for (_, path) in backups.iter().skip(settings.keep) {
+        tokio::fs::remove_file(path).await?;
+        removed += 1;
+    }
+
+    if removed != 0 {
+        info!("Removed {} old backup(s)", removed);
+    }
+
+    Ok(removed)
+}
+
+/// The backups in the directory with their timestamp, newest first
+async fn list_backups(dir: &Path) -> Result<Vec<(u64, PathBuf)>, Error> {
+    let mut backups = vec![];
+
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let stamp = name.strip_prefix(BACKUP_PREFIX)
+            .and_then(|n| n.strip_suffix(BACKUP_SUFFIX))
+            .and_then(parse_backup_stamp);
+
+        if let Some(stamp) = stamp {
+            backups.push((stamp, entry.path()));
+        }
+    }
+
+    backups.sort_by_key(|b| std::cmp::Reverse(b.0));
+
+    Ok(backups.into_iter().map(|((stamp, _), path)| (stamp, path)).collect())
+}
+
+/// The timestamp of a backup and its counter, 0 for the first backup of that second
+fn parse_backup_stamp(name: &str) -> Option<(u64, u64)> {
+    let (stamp, count) = name.split_once('-').unwrap_or((name, "0"));
+
+    Some((stamp.parse().ok()?, count.parse().ok()?))
+}
+
+/// Overwrites the database at `db_url` with the given backup, only meant to run before the bot starts.
+/// A write-ahead log left next to the database is removed first, it belongs to the data that gets replaced.
+pub async fn restore_backup(backup: &Path, db_url: &str) -> Result<(), Error> {
+    if Backend::from_url(db_url)? != Backend::Sqlite {
+        return Err("Backups can only be restored into a SQLite database".into());
+    }
+
+    let mut conn = SqliteConnectOptions::new()
+        .filename(backup)
+        .read_only(true)
+        .connect()
+        .await?;
+
+    let problems = integrity_check(&mut conn).await?;
+    if !problems.is_empty() {
+        return Err(format!("{} is damaged - {}", backup.display(), problems.join(", ")).into());
+    }
+
+    let target = database_file(db_url);
+
+    // The log and shared memory of the old database would be replayed over the restored one
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = target.clone().into_os_string();
+        sidecar.push(suffix);
+
+        match tokio::fs::remove_file(&sidecar).await {
+            Ok(()) => info!("Removed {}", Path::new(&sidecar).display()),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    copy_database(&mut conn, &target).await?;
+
+    info!("Restored the database from {}", backup.display());
+
+    Ok(())
+}
+
+/// The file behind a `sqlite:` database url
+fn database_file(db_url: &str) -> PathBuf {
+    let path = db_url.trim_start_matches("sqlite:").trim_start_matches("//");
+
+    path.split('?').next().unwrap_or(path).into()
+}
+
+/// Runs `PRAGMA integrity_check`, an empty list means the database is fine
+pub async fn integrity_check<'c>(conn: impl Into<DbConn<'c>>) -> Result<Vec<String>, Error> {
+    let conn = &mut conn.into();
+
+    if conn.backend() != Backend::Sqlite {
+        return Err("Integrity checks are only available for SQLite databases".into());
+    }
+
+    let rows = query_as::<(String,)>("PRAGMA integrity_check;")
+        .fetch_all(conn)
+        .await?;
+
+    Ok(rows.into_iter().map(|(r,)| r).filter(|r| r != "ok").collect())
+}
+
+/// Rebuilds the database file to give back the space of deleted rows, returns the size before and after in bytes
+pub async fn vacuum<'c>(conn: impl Into<DbConn<'c>>) -> Result<(i64, i64), Error> {
+    let conn = &mut conn.into();
+    let before = database_size(conn).await?;
+
+    conn.execute_script("VACUUM;").await?;
+
+    Ok((before, database_size(conn).await?))
+}
+
+async fn database_size(conn: &mut DbConn<'_>) -> Result<i64, Error> {
+    let sql = match conn.backend() {
+        Backend::Sqlite => "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size();",
+        Backend::Postgres => "SELECT pg_database_size(current_database());",
+    };
+
+    let (size,) = query_as::<(i64,)>(sql).fetch_one(conn).await?;
+
+    Ok(size)
+}
+
+/// Pages copied by a single backup step
+const PAGES_PER_STEP: c_int = 256;
+
+/// A backup that is in progress and the database it is written to
+struct OpenBackup {
+    target: *mut ffi::sqlite3,
+    backup: *mut ffi::sqlite3_backup,
+}
+
+// SAFETY: the handles are only ever used by the task that opened them, one call at a time
+unsafe impl Send for OpenBackup {}
+
+impl Drop for OpenBackup {
+    fn drop(&mut self) {
+        // SAFETY: both were opened by copy_database and are not used after this
+        unsafe {
+            ffi::sqlite3_backup_finish(self.backup);
+            ffi::sqlite3_close(self.target);
+        }
+    }
+}
+
+/// Copies the main database of `conn` into the file at `dest`, replacing whatever was there
+async fn copy_database(conn: &mut SqliteConnection, dest: &Path) -> Result<(), Error> {
+    let dest = CString::new(dest.to_str().ok_or("Database path is not valid UTF-8")?)?;
+
+    let mut handle = conn.lock_handle().await?;
+
+    // SAFETY: the source handle stays valid while it is locked, the destination is opened in here and closed by OpenBackup
+    let open = unsafe {
+        let source = handle.as_raw_handle().as_ptr();
+        let mut target = ptr::null_mut();
+        let flags = ffi::SQLITE_OPEN_READWRITE | ffi::SQLITE_OPEN_CREATE;
+
+        if ffi::sqlite3_open_v2(dest.as_ptr(), &mut target, flags, ptr::null()) != ffi::SQLITE_OK {
+            let err = last_error(target);
+            ffi::sqlite3_close(target);
+            return Err(err);
+        }
+
+        let backup = ffi::sqlite3_backup_init(target, c"main".as_ptr(), source, c"main".as_ptr());
+        if backup.is_null() {
+            let err = last_error(target);
+            ffi::sqlite3_close(target);
+            return Err(err);
+        }
+
+        OpenBackup { target, backup }
+    };
+
+    // Copied a chunk at a time, waiting happens on the runtime instead of blocking the thread it runs on
+    let mut busy = 0;
+    let result = loop {
+        // SAFETY: the backup stays open until `open` is dropped
+        match unsafe { ffi::sqlite3_backup_step(open.backup, PAGES_PER_STEP) } {
+            ffi::SQLITE_DONE => break Ok(()),
+            ffi::SQLITE_OK => tokio::task::yield_now().await,
+            ffi::SQLITE_BUSY | ffi::SQLITE_LOCKED if busy < 20 => {
+                busy += 1;
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            // SAFETY: sqlite3_errstr returns a static string for any result code
+            code => break Err(unsafe { CStr::from_ptr(ffi::sqlite3_errstr(code)) }.to_string_lossy().to_string().into()),
+        }
+    };
+
+    // The backup has to be finished while the source is still locked
+    drop(open);
+    drop(handle);
+
+    result
+}
+
+/// SAFETY: `db` has to be a handle returned by sqlite3_open_v2, null is allowed
+unsafe fn last_error(db: *mut ffi::sqlite3) -> Error {
+    match db.is_null() {
+        true => "Out of memory opening the database".into(),
+        false => CStr::from_ptr(ffi::sqlite3_errmsg(db)).to_string_lossy().to_string().into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::build_db::build_tables;
+    use crate::db_handlers::color_repo::ColorRepo;
+    use crate::db_handlers::ids::{DbGuildId, DbRoleId, DbUserId};
+    use sqlx::sqlite::SqliteJournalMode;
+    use sqlx::Connection;
+
+    #[tokio::test]
+    async fn backups_can_be_restored_and_pruned() {
+        let dir = std::env::temp_dir().join(format!("cyberbun-test-{}", std::process::id()));
+        let settings = BackupSettings { dir: dir.clone(), interval: None, keep: 1 };
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        build_tables(&mut conn).await.unwrap();
+        ColorRepo::new(&mut conn).create(DbRoleId(1), DbUserId(2), DbGuildId(3), 0xff0000, "red".to_string()).await.unwrap();
+
+        let backup = dir.join(format!("{}1{}", BACKUP_PREFIX, BACKUP_SUFFIX));
+        copy_database(&mut conn, &backup).await.unwrap();
+        tokio::fs::write(dir.join(format!("{}0{}", BACKUP_PREFIX, BACKUP_SUFFIX)), b"").await.unwrap();
+
+        let restored = dir.join("restored.db");
+        restore_backup(&backup, &format!("sqlite://{}", restored.display())).await.unwrap();
+
+        let mut conn = SqliteConnectOptions::new().filename(&restored).connect().await.unwrap();
+        assert!(integrity_check(&mut conn).await.unwrap().is_empty());
+        let row = ColorRepo::new(&mut conn).get(DbUserId(2), DbGuildId(3)).await.unwrap().unwrap();
+        assert_eq!(row.color, 0xff0000);
+
+        assert_eq!(prune_backups(&settings).await.unwrap(), 1);
+        assert!(backup.exists());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn backups_in_the_same_second_are_all_kept() {
+        let dir = std::env::temp_dir().join(format!("cyberbun-same-second-test-{}", std::process::id()));
+        let settings = BackupSettings { dir: dir.clone(), interval: None, keep: 10 };
+
+        let sqlite = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        let pool = DbPool::from(sqlite);
+        build_tables(&mut pool.acquire().await.unwrap()).await.unwrap();
+
+        let mut paths = vec![];
+        for _ in 0..3 {
+            paths.push(backup_now(&pool, &settings).await.unwrap());
+        }
+
+        // Newest first, the counter breaks the tie within a second
+        let listed: Vec<PathBuf> = list_backups(&dir).await.unwrap().into_iter().map(|(_, path)| path).collect();
+        assert_eq!(listed, paths.into_iter().rev().collect::<Vec<_>>());
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[test]
+    fn backup_names_sort_by_time_then_counter() {
+        assert_eq!(parse_backup_stamp("1700000000"), Some((1700000000, 0)));
+        assert_eq!(parse_backup_stamp("1700000000-2"), Some((1700000000, 2)));
+        assert_eq!(parse_backup_stamp("1700000000-x"), None);
+        assert_eq!(parse_backup_stamp("latest"), None);
+    }
+
+    #[tokio::test]
+    async fn restoring_drops_a_stale_write_ahead_log() {
+        let dir = std::env::temp_dir().join(format!("cyberbun-wal-test-{}", std::process::id()));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        build_tables(&mut conn).await.unwrap();
+        ColorRepo::new(&mut conn).create(DbRoleId(1), DbUserId(2), DbGuildId(3), 0xff0000, "red".to_string()).await.unwrap();
+        let backup = dir.join("backup.db");
+        copy_database(&mut conn, &backup).await.unwrap();
+
+        // A database in WAL mode whose last changes are still in its log
+        let restored = dir.join("restored.db");
+        let wal = dir.join("restored.db-wal");
+        let options = SqliteConnectOptions::new().filename(&restored).create_if_missing(true).journal_mode(SqliteJournalMode::Wal);
+        let mut live = options.connect().await.unwrap();
+        build_tables(&mut live).await.unwrap();
+        ColorRepo::new(&mut live).create(DbRoleId(4), DbUserId(5), DbGuildId(6), 0x00ff00, "green".to_string()).await.unwrap();
+        let log = tokio::fs::read(&wal).await.unwrap();
+        live.close().await.unwrap();
+        tokio::fs::write(&wal, log).await.unwrap();
+
+        restore_backup(&backup, &format!("sqlite://{}", restored.display())).await.unwrap();
+        assert!(!wal.exists());
+
+        let mut conn = SqliteConnectOptions::new().filename(&restored).connect().await.unwrap();
+        assert!(integrity_check(&mut conn).await.unwrap().is_empty());
+        assert!(ColorRepo::new(&mut conn).get(DbUserId(2), DbGuildId(3)).await.unwrap().is_some());
+        assert!(ColorRepo::new(&mut conn).get(DbUserId(5), DbGuildId(6)).await.unwrap().is_none());
+        conn.close().await.unwrap();
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
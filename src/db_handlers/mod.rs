@@ -1,4 +1,65 @@
+pub mod backend;
 pub mod build_db;
-pub mod color_handlers;
-pub mod starboard_handlers;
-pub mod reminder_handlers;
\ No newline at end of file
+pub mod color_cycle_repo;
+pub mod color_job_repo;
+pub mod color_repo;
+pub mod guild_settings_repo;
+pub mod ids;
+pub mod maintenance;
+pub mod privacy_repo;
+pub mod reminder_repo;
+pub mod starboard_repo;
+
+/// Runs a test on a fresh database of every backend - in-memory SQLite, and Postgres when `CYBERBUN_TEST_PG_URL` points at one.
+/// Every Postgres run gets a schema of its own, which is dropped again once the test passed.
+#[cfg(test)]
+pub async fn for_each_backend(test: impl AsyncFn(&backend::DbPool)) {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use sqlx::postgres::{PgPool, PgPoolOptions};
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::Executor;
+
+    // An in-memory database lives as long as its connection, so the pool keeps a single one open
+    let sqlite = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+
+    let pool = backend::DbPool::from(sqlite);
+    build_db::build_tables(&mut pool.acquire().await.unwrap()).await.unwrap();
+    test(&pool).await;
+
+    let Ok(url) = std::env::var("CYBERBUN_TEST_PG_URL") else {
+        return;
+    };
+
+    static SCHEMAS: AtomicUsize = AtomicUsize::new(0);
+    let schema = format!("cyberbun_test_{}_{}", std::process::id(), SCHEMAS.fetch_add(1, Ordering::Relaxed));
+
+    let admin = PgPool::connect(&url).await.unwrap();
+    admin.execute(format!("CREATE SCHEMA {};", schema).as_str()).await.unwrap();
+
+    let search_path = format!("SET search_path TO {};", schema);
+    let postgres = PgPoolOptions::new()
+        .after_connect(move |conn, _| {
+            let search_path = search_path.clone();
+            Box::pin(async move {
+                conn.execute(search_path.as_str()).await?;
+                Ok(())
+            })
+        })
+        .connect(&url)
+        .await
+        .unwrap();
+
+    let pool = backend::DbPool::from(postgres.clone());
+    build_db::build_tables(&mut pool.acquire().await.unwrap()).await.unwrap();
+    test(&pool).await;
+
+    postgres.close().await;
+    admin.execute(format!("DROP SCHEMA {} CASCADE;", schema).as_str()).await.unwrap();
+}
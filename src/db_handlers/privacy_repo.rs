@@ -0,0 +1,389 @@
+//! Everything stored about a single user, gathered for `/privacy export` and erased by `/privacy delete`,
+//! everything about a guild that removed the bot, and the retention purge that drops rows nobody needs anymore.
+//! The starboard only keeps message and guild ids, nothing in it points back to a user.
+
+use serde::Serialize;
+
+use super::super::Error;
+use super::backend::{query, query_as, DbConn};
+use super::ids::{DbChannelId, DbGuildId, DbRoleId, DbUserId};
+
+/// The export sent to a user, ids are strings so they survive tools that read numbers as floats.
+/// Times are unix timestamps in seconds.
+#[derive(Serialize)]
+pub struct UserData {
+    pub user_id: String,
+    pub color_roles: Vec<ExportedColorRole>,
+    pub color_history: Vec<ExportedColorChange>,
+    pub color_preferences: Vec<ExportedColorPreferences>,
+    pub color_cycles: Vec<ExportedColorCycle>,
+    pub scheduled_colors: Vec<ExportedScheduledColor>,
+    /// Guilds whose admins denied this user a color role
+    pub color_denied_in: Vec<String>,
+    pub reminders: Vec<ExportedReminder>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedColorRole {
+    pub guild_id: String,
+    pub role_id: String,
+    pub color: String,
+    pub role_name: String,
+}
+
+#[derive(Serialize)]
+pub struct ExportedColorChange {
+    pub guild_id: String,
+    pub old_color: Option<String>,
+    pub color: String,
+    pub source: String,
+    pub timestamp: i64,
+}
+
+#[derive(Serialize)]
+pub struct ExportedColorPreferences {
+    pub guild_id: String,
+    /// 0 off, 1 ask first, 2 on
+    pub steal_protection: i64,
+    pub last_steal: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedColorCycle {
+    pub guild_id: String,
+    pub colors: String,
+    pub interval: i64,
+    pub next_run: i64,
+}
+
+/// A temporary color that is still going to be reverted
+#[derive(Serialize)]
+pub struct ExportedScheduledColor {
+    pub guild_id: String,
+    pub revert_to: Option<String>,
+    pub run_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct ExportedReminder {
+    pub channel_id: String,
+    pub timestamp: i64,
+    pub message: String,
+    pub private: bool,
+    pub completed: bool,
+}
+
+/// How many rows a retention purge removed, per table
+#[derive(Default, Debug)]
+pub struct PurgedRows {
+    pub reminders: u64,
+    pub color_history: u64,
+    pub color_members: u64,
+    /// Rows of guilds whose grace period ran out
+    pub left_guilds: u64,
+}
+
+impl PurgedRows {
+    pub fn total(&self) -> u64 {
+        self.reminders + self.color_history + self.color_members + self.left_guilds
+    }
+}
+
+fn hex(color: i64) -> String {
+    format!("#{:06x}", color)
+}
+
+pub struct PrivacyRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> PrivacyRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        PrivacyRepo { conn: conn.into() }
+    }
+
+    pub async fn export_user(&mut self, uid: DbUserId) -> Result<UserData, Error> {
+        let color_roles = query_as::<(DbGuildId, DbRoleId, i64, String)>(
+            "SELECT guid, role_id, color, role_name FROM colors WHERE uid = $1 ORDER BY guid;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guild, role, color, role_name)| ExportedColorRole {
+            guild_id: guild.to_string(),
+            role_id: role.to_string(),
+            color: hex(color),
+            role_name,
+        })
+        .collect();
+
+        let color_history = query_as::<(DbGuildId, Option<i64>, i64, String, i64)>(
+            "SELECT guid, old_color, color, source, timestamp FROM color_history WHERE uid = $1 ORDER BY id;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guild, old_color, color, source, timestamp)| ExportedColorChange {
+            guild_id: guild.to_string(),
+            old_color: old_color.map(hex),
+            color: hex(color),
+            source,
+            timestamp,
+        })
+        .collect();
+
+        let color_preferences = query_as::<(DbGuildId, i64, Option<i64>)>(
+            "SELECT guid, steal_protection, last_steal FROM color_members WHERE uid = $1 ORDER BY guid;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guild, steal_protection, last_steal)| ExportedColorPreferences {
+            guild_id: guild.to_string(),
+            steal_protection,
+            last_steal,
+        })
+        .collect();
+
+        let color_cycles = query_as::<(DbGuildId, String, i64, i64)>(
+            "SELECT guid, colors, interval, next_run FROM color_cycles WHERE uid = $1 ORDER BY guid;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guild, colors, interval, next_run)| ExportedColorCycle {
+            guild_id: guild.to_string(),
+            colors,
+            interval,
+            next_run,
+        })
+        .collect();
+
+        let scheduled_colors = query_as::<(DbGuildId, Option<i64>, i64)>(
+            "SELECT guid, color, run_at FROM color_jobs WHERE uid = $1 ORDER BY run_at;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guild, color, run_at)| ExportedScheduledColor {
+            guild_id: guild.to_string(),
+            revert_to: color.map(hex),
+            run_at,
+        })
+        .collect();
+
+        let color_denied_in = query_as::<(DbGuildId,)>(
+            "SELECT guild_id FROM color_rules WHERE kind = 'denied_user' AND target = $1 ORDER BY guild_id;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(guild,)| guild.to_string())
+        .collect();
+
+        let reminders = query_as::<(DbChannelId, i64, String, bool, bool)>(
+            "SELECT channel_id, timestamp, message, private, completed FROM reminders WHERE user_id = $1 ORDER BY timestamp;",
+        )
+        .bind(uid)
+        .fetch_all(&mut self.conn)
+        .await?
+        .into_iter()
+        .map(|(channel, timestamp, message, private, completed)| ExportedReminder {
+            channel_id: channel.to_string(),
+            timestamp,
+            message,
+            private,
+            completed,
+        })
+        .collect();
+
+        Ok(UserData {
+            user_id: uid.to_string(),
+            color_roles,
+            color_history,
+            color_preferences,
+            color_cycles,
+            scheduled_colors,
+            color_denied_in,
+            reminders,
+        })
+    }
+
+    /// Erases all rows about the user and returns how many there were.
+    /// Color roles have to be deleted from their guilds before, this only drops what is left of them in the database.
+    /// Deny list entries stay, they are the decision of a guild's admins and not the user's data to take back.
+    pub async fn delete_user(&mut self, uid: DbUserId) -> Result<u64, Error> {
+        // What belongs to a color role goes with it, so it is deleted first to still count it
+        let statements = [
+            "DELETE FROM color_history WHERE uid = $1;",
+            "DELETE FROM color_members WHERE uid = $1;",
+            "DELETE FROM color_cycles WHERE uid = $1;",
+            "DELETE FROM color_jobs WHERE uid = $1;",
+            "DELETE FROM colors WHERE uid = $1;",
+            "DELETE FROM reminders WHERE user_id = $1;",
+        ];
+
+        let mut deleted = 0;
+        for sql in statements {
+            deleted += query(sql).bind(uid).execute(&mut self.conn).await?.rows_affected();
+        }
+
+        Ok(deleted)
+    }
+
+    /// Erases all rows of a guild, returns how many there were.
+    /// Reminders set before their guild was recorded only know their channel, they stay until they are due.
+    pub async fn delete_guild(&mut self, guid: DbGuildId) -> Result<u64, Error> {
+        let statements = [
+            "DELETE FROM color_history WHERE guid = $1;",
+            "DELETE FROM color_members WHERE guid = $1;",
+            "DELETE FROM color_cycles WHERE guid = $1;",
+            "DELETE FROM color_jobs WHERE guid = $1;",
+            "DELETE FROM colors WHERE guid = $1;",
+            "DELETE FROM abandoned_roles WHERE guid = $1;",
+            "DELETE FROM color_rules WHERE guild_id = $1;",
+            "DELETE FROM color_settings WHERE guild_id = $1;",
+            "DELETE FROM guild_settings WHERE guild_id = $1;",
+            "DELETE FROM starred_messages WHERE guild_id = $1;",
+            "DELETE FROM reminders WHERE guild_id = $1;",
+            "DELETE FROM left_guilds WHERE guild_id = $1;",
+        ];
+
+        let mut deleted = 0;
+        for sql in statements {
+            deleted += query(sql).bind(guid).execute(&mut self.conn).await?.rows_affected();
+        }
+
+        Ok(deleted)
+    }
+
+    /// Drops completed reminders and color history from before `cutoff`,
+    /// and member preferences that are back at their defaults with no steal since then
+    pub async fn purge_before(&mut self, cutoff: u64) -> Result<PurgedRows, Error> {
+        let reminders = query("DELETE FROM reminders WHERE completed = TRUE AND timestamp < $1;")
+            .bind(cutoff as i64)
+            .execute(&mut self.conn)
+            .await?
+            .rows_affected();
+
+        let color_history = query("DELETE FROM color_history WHERE timestamp < $1;")
+            .bind(cutoff as i64)
+            .execute(&mut self.conn)
+            .await?
+            .rows_affected();
+
+        let color_members = query("DELETE FROM color_members WHERE steal_protection = 0 AND (last_steal IS NULL OR last_steal < $1);")
+            .bind(cutoff as i64)
+            .execute(&mut self.conn)
+            .await?
+            .rows_affected();
+
+        Ok(PurgedRows { reminders, color_history, color_members, left_guilds: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::color_repo::{ColorRepo, ColorSource, StealProtection};
+    use crate::db_handlers::for_each_backend;
+    use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+    use crate::db_handlers::ids::DbMessageId;
+    use crate::db_handlers::reminder_repo::{NewReminder, ReminderRepo};
+    use crate::db_handlers::starboard_repo::StarboardRepo;
+
+    const GUILD: DbGuildId = DbGuildId(10);
+    const USER: DbUserId = DbUserId(20);
+    const OTHER: DbUserId = DbUserId(21);
+
+    fn reminder(user_id: DbUserId, timestamp: u64) -> NewReminder {
+        NewReminder { timestamp, message: "stretch".to_string(), user_id, channel_id: DbChannelId(2), guild_id: Some(GUILD), private: false }
+    }
+
+    #[tokio::test]
+    async fn users_are_exported_and_deleted_alone() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+
+            let mut colors = ColorRepo::new(&mut conn);
+            colors.create(DbRoleId(30), USER, GUILD, 0xff0000, "red".to_string()).await.unwrap();
+            colors.create(DbRoleId(31), OTHER, GUILD, 0x00ff00, "green".to_string()).await.unwrap();
+            colors.add_history(USER, GUILD, None, 0xff0000, ColorSource::Set).await.unwrap();
+            colors.set_steal_protection(USER, GUILD, StealProtection::On).await.unwrap();
+            colors.set_denied_user(GUILD, USER, true).await.unwrap();
+            ReminderRepo::new(&mut conn).create(reminder(USER, 0)).await.unwrap();
+            ReminderRepo::new(&mut conn).create(reminder(OTHER, 0)).await.unwrap();
+
+            let mut repo = PrivacyRepo::new(&mut conn);
+            let data = repo.export_user(USER).await.unwrap();
+            assert_eq!(data.color_roles.len(), 1);
+            assert_eq!(data.color_roles[0].color, "#ff0000");
+            assert_eq!(data.color_history.len(), 1);
+            assert_eq!(data.color_preferences[0].steal_protection, 2);
+            assert_eq!(data.color_denied_in, vec![GUILD.to_string()]);
+            assert_eq!(data.reminders.len(), 1);
+
+            assert_eq!(repo.delete_user(USER).await.unwrap(), 4);
+
+            let data = repo.export_user(USER).await.unwrap();
+            assert!(data.color_roles.is_empty() && data.color_history.is_empty() && data.reminders.is_empty());
+            assert_eq!(data.color_denied_in.len(), 1);
+            assert_eq!(repo.export_user(OTHER).await.unwrap().color_roles.len(), 1);
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn purge_keeps_pending_reminders_and_preferences() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+
+            let mut reminders = ReminderRepo::new(&mut conn);
+            reminders.create(reminder(USER, 0)).await.unwrap();
+            reminders.create(reminder(USER, 1)).await.unwrap();
+            let done = reminders.get_expired().await.unwrap()[0].id;
+            reminders.set_completed(done).await.unwrap();
+
+            let mut colors = ColorRepo::new(&mut conn);
+            colors.add_history(USER, GUILD, None, 1, ColorSource::Set).await.unwrap();
+            colors.set_steal_protection(USER, GUILD, StealProtection::Ask).await.unwrap();
+            colors.set_last_steal(OTHER, GUILD).await.unwrap();
+
+            let purged = PrivacyRepo::new(&mut conn).purge_before(u32::MAX as u64 * 4).await.unwrap();
+            assert_eq!((purged.reminders, purged.color_history, purged.color_members), (1, 1, 1));
+
+            assert_eq!(ReminderRepo::new(&mut conn).get_expired().await.unwrap().len(), 1);
+            assert!(ColorRepo::new(&mut conn).get_member(USER, GUILD).await.unwrap().is_some());
+        }).await;
+    }
+
+    #[tokio::test]
+    async fn guilds_are_deleted_alone() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+
+            let mut colors = ColorRepo::new(&mut conn);
+            colors.create(DbRoleId(30), USER, GUILD, 1, "one".to_string()).await.unwrap();
+            colors.create(DbRoleId(31), USER, DbGuildId(11), 1, "one".to_string()).await.unwrap();
+            colors.set_required_role(GUILD, DbRoleId(40), true).await.unwrap();
+
+            let mut settings = GuildSettingsRepo::new(&mut conn);
+            settings.set_anchor_role(GUILD, Some(DbRoleId(41))).await.unwrap();
+            settings.mark_left(GUILD).await.unwrap();
+            StarboardRepo::new(&mut conn).insert_message(GUILD, DbMessageId(50)).await.unwrap();
+            ReminderRepo::new(&mut conn).create(reminder(USER, 0)).await.unwrap();
+
+            assert_eq!(PrivacyRepo::new(&mut conn).delete_guild(GUILD).await.unwrap(), 6);
+
+            assert!(GuildSettingsRepo::new(&mut conn).get_color_settings(GUILD).await.unwrap().is_none());
+            assert!(!StarboardRepo::new(&mut conn).message_exists(DbMessageId(50)).await.unwrap());
+            assert!(ReminderRepo::new(&mut conn).get_expired().await.unwrap().is_empty());
+            assert_eq!(PrivacyRepo::new(&mut conn).export_user(USER).await.unwrap().color_roles.len(), 1);
+        }).await;
+    }
+}
@@ -0,0 +1,119 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::Error;
+use super::ids::{DbChannelId, DbGuildId, DbUserId};
+use super::backend::{query, query_as, DbConn};
+
+#[derive(sqlx::FromRow)]
+pub struct Reminder {
+    pub id: i64,
+    pub message: String,
+    pub user_id: DbUserId,
+    pub channel_id: DbChannelId,
+}
+
+pub struct NewReminder {
+    pub timestamp: u64,
+    pub message: String,
+    pub user_id: DbUserId,
+    pub channel_id: DbChannelId,
+    /// None for reminders set in DMs
+    pub guild_id: Option<DbGuildId>,
+    pub private: bool,
+}
+
+pub struct ReminderRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> ReminderRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        ReminderRepo { conn: conn.into() }
+    }
+
+    /// Reminders that are not completed yet and whose time has come
+    pub async fn get_expired(&mut self) -> Result<Vec<Reminder>, Error> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Error, time went backwards").as_secs();
+
+        let result = query_as::<Reminder>(
+            r#"
+            SELECT id, message, user_id, channel_id FROM reminders
+            WHERE completed = FALSE AND timestamp <= $1;
+            "#,
+        )
+        .bind(now as i64)
+        .fetch_all(&mut self.conn)
+        .await?;
+
+        Ok(result)
+    }
+
+    pub async fn set_completed(&mut self, id: i64) -> Result<(), Error> {
+        query(
+            r#"
+            UPDATE reminders
+            SET completed = true
+            WHERE id = $1;
+            "#,
+        )
+        .bind(id)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn create(&mut self, new: NewReminder) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO reminders (timestamp, message, user_id, channel_id, guild_id, private, completed)
+            VALUES ($1, $2, $3, $4, $5, $6, FALSE);
+            "#
+        )
+        .bind(new.timestamp as i64)
+        .bind(new.message)
+        .bind(new.user_id)
+        .bind(new.channel_id)
+        .bind(new.guild_id)
+        .bind(new.private)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::for_each_backend;
+
+    fn reminder(timestamp: u64) -> NewReminder {
+        NewReminder {
+            timestamp,
+            message: "stretch".to_string(),
+            user_id: DbUserId(1),
+            channel_id: DbChannelId(2),
+            guild_id: None,
+            private: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_due_reminders_expire_once() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = ReminderRepo::new(&mut conn);
+
+            repo.create(reminder(0)).await.unwrap();
+            repo.create(reminder(u32::MAX as u64 * 4)).await.unwrap();
+
+            let expired = repo.get_expired().await.unwrap();
+            assert_eq!(expired.len(), 1);
+            assert_eq!(expired[0].user_id, DbUserId(1));
+
+            repo.set_completed(expired[0].id).await.unwrap();
+            assert!(repo.get_expired().await.unwrap().is_empty());
+        }).await;
+    }
+}
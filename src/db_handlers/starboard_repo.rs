@@ -0,0 +1,60 @@
+use super::super::Error;
+use super::ids::{DbGuildId, DbMessageId};
+use super::backend::{query, query_as, DbConn};
+
+/// Messages that made it onto a starboard, so they only get posted once
+pub struct StarboardRepo<'c> {
+    conn: DbConn<'c>,
+}
+
+impl<'c> StarboardRepo<'c> {
+    pub fn new(conn: impl Into<DbConn<'c>>) -> Self {
+        StarboardRepo { conn: conn.into() }
+    }
+
+    pub async fn message_exists(&mut self, msg_id: DbMessageId) -> Result<bool, Error> {
+        let result = query_as::<(DbMessageId,)>(
+            r#"
+            SELECT msg_id FROM starred_messages WHERE msg_id = $1;
+            "#
+        )
+        .bind(msg_id)
+        .fetch_optional(&mut self.conn)
+        .await?;
+
+        Ok(result.is_some())
+    }
+
+    pub async fn insert_message(&mut self, guid: DbGuildId, msg_id: DbMessageId) -> Result<(), Error> {
+        query(
+            r#"
+            INSERT INTO starred_messages (msg_id, guild_id)
+            VALUES ($1, $2);
+            "#
+        )
+        .bind(msg_id)
+        .bind(guid)
+        .execute(&mut self.conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db_handlers::for_each_backend;
+
+    #[tokio::test]
+    async fn messages_are_remembered() {
+        for_each_backend(async |pool| {
+            let mut conn = pool.acquire().await.unwrap();
+            let mut repo = StarboardRepo::new(&mut conn);
+
+            assert!(!repo.message_exists(DbMessageId(1)).await.unwrap());
+            repo.insert_message(DbGuildId(10), DbMessageId(1)).await.unwrap();
+            assert!(repo.message_exists(DbMessageId(1)).await.unwrap());
+        }).await;
+    }
+}
@@ -1,21 +1,29 @@
-use crate::db_handlers::color_handlers::get_color;
-use crate::db_handlers::reminder_handlers::{get_expired_reminders, set_completed, Reminder};
-use crate::db_handlers::starboard_handlers::{get_guild_settings, insert_message, message_exists};
-use poise::serenity_prelude::{CreateEmbedFooter, CreateMessage, Mentionable, UserId};
+use crate::color_roles::audit_log::{log_color_change, ColorChange};
+use crate::color_roles::cycle::{run_next_cycle, stop_member_cycle, CYCLE_TICK};
+use crate::color_roles::eligibility::check_eligibility;
+use crate::color_roles::hierarchy::{describe_error, preflight};
+use crate::color_roles::jobs::run_due_jobs;
+use crate::color_roles::naming::sync_role_name;
+use crate::color_roles::reconcile::{find_drift, report_drift};
+use crate::color_roles::{is_not_found, restore_color_role};
+use crate::db_handlers::backend::DbPool;
+use crate::db_handlers::color_repo::{ColorRepo, ColorSource};
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::db_handlers::maintenance::{run_retention, run_scheduled_backup};
+use crate::db_handlers::reminder_repo::{Reminder, ReminderRepo};
+use crate::db_handlers::starboard_repo::StarboardRepo;
+use poise::serenity_prelude::{CreateEmbedFooter, CreateMessage, Mentionable, RoleId, UserId};
 use poise::{
     serenity_prelude::{
         ActivityData, ChannelId, Context, CreateEmbed, FullEvent, ReactionType, Timestamp,
     },
     FrameworkContext,
 };
-use sqlx::Pool;
 use std::sync::atomic::Ordering::SeqCst;
 use std::sync::Arc;
 use tracing::info;
 use tokio::time::Duration;
 
-use sqlx::Sqlite;
-
 use super::{Data, Error};
 
 pub async fn event_handler(
@@ -40,30 +48,91 @@ pub async fn event_handler(
         }
 
         FullEvent::CacheReady { guilds: _ } => {
-
             if !data.reminder_task_running.load(SeqCst) {
                 let reminder_ctx = Arc::new(ctx.clone());
                 let pool = Arc::from(data.pool.clone());
+                let interval = data.config.reminder_interval;
 
                 data.reminder_task_running.store(true, SeqCst);
                 tokio::spawn(async move {
                     loop {
                         // Reminders event -
                         // Check Database for first upcoming reminder
-                        let status = reminder_handler(&reminder_ctx, &pool).await;
-                        
-                        match status {
-                            Ok(_) => continue,
-                            Err(err) => info!("Error occured in reminder loop - {}", err)
+                        if let Err(err) = reminder_handler(&reminder_ctx, &pool).await {
+                            info!("Error occured in reminder loop - {}", err);
+                        }
+                        tokio::time::sleep(interval).await;
+                    }
+                });
+            }
+
+            if !data.color_job_task_running.load(SeqCst) {
+                let job_ctx = Arc::new(ctx.clone());
+                let pool = Arc::from(data.pool.clone());
+
+                data.color_job_task_running.store(true, SeqCst);
+                tokio::spawn(async move {
+                    loop {
+                        // Temporary colors and color themes that are due
+                        if let Err(err) = run_due_jobs(&job_ctx.http, &pool).await {
+                            info!("Error occured in color job loop - {}", err);
                         }
                         tokio::time::sleep(Duration::from_secs(30)).await;
                     }
                 });
             }
-        } 
 
-        FullEvent::GuildCreate { guild: _, is_new } => match is_new {
-            Some(true) => {
+            if !data.color_cycle_task_running.load(SeqCst) {
+                let cycle_ctx = Arc::new(ctx.clone());
+                let pool = Arc::from(data.pool.clone());
+
+                data.color_cycle_task_running.store(true, SeqCst);
+                tokio::spawn(async move {
+                    loop {
+                        // Cycling colors - one role edit per tick keeps all guilds within the rate budget
+                        if let Err(err) = run_next_cycle(&cycle_ctx.http, &pool).await {
+                            info!("Error occured in color cycle loop - {}", err);
+                        }
+                        tokio::time::sleep(CYCLE_TICK).await;
+                    }
+                });
+            }
+
+            let backups = data.config.backups.clone();
+            if backups.interval.is_some() && !data.backup_task_running.load(SeqCst) {
+                let pool = Arc::from(data.pool.clone());
+
+                data.backup_task_running.store(true, SeqCst);
+                tokio::spawn(async move {
+                    loop {
+                        // Backups go by the age of the newest one, checking every hour is plenty
+                        if let Err(err) = run_scheduled_backup(&pool, &backups).await {
+                            info!("Error occured in backup loop - {}", err);
+                        }
+                        tokio::time::sleep(Duration::from_secs(60 * 60)).await;
+                    }
+                });
+            }
+
+            if !data.retention_task_running.load(SeqCst) {
+                let retention = data.config.retention.clone();
+                let pool = Arc::from(data.pool.clone());
+
+                data.retention_task_running.store(true, SeqCst);
+                tokio::spawn(async move {
+                    loop {
+                        // Rows are kept for days, purging a few times a day is plenty
+                        if let Err(err) = run_retention(&pool, &retention).await {
+                            info!("Error occured in retention loop - {}", err);
+                        }
+                        tokio::time::sleep(Duration::from_secs(6 * 60 * 60)).await;
+                    }
+                });
+            }
+        }
+
+        FullEvent::GuildCreate { guild, is_new } => {
+            if let Some(true) = is_new {
                 info!("Joined 1 new server!");
 
                 let new_count = data.server_count.load(SeqCst) + 1;
@@ -74,16 +143,35 @@ pub async fn event_handler(
                     new_count
                 ))));
             }
-            Some(false) => (),
-            _ => (),
-        },
+
+            // Added back within the grace period, everything is still there
+            if GuildSettingsRepo::new(&mut data.pool.acquire().await?).mark_returned(guild.id.into()).await? {
+                info!("Got added back to {}, keeping its data", guild.name);
+            }
+
+            // Color roles might have been changed while the bot was offline - only reported, repairing can delete roles
+            let drift = find_drift(&ctx.http, &data.pool, guild.id).await?;
+            for d in drift.iter() {
+                info!("Color drift in {} - {}", guild.name, d);
+            }
+
+            if !drift.is_empty() {
+                report_drift(&ctx.http, &data.pool, guild.id, &drift).await?;
+            }
+        }
 
         FullEvent::GuildDelete { incomplete, full: _, } => {
             let new_count = data.server_count.load(SeqCst) - 1;
             data.server_count.store(new_count, SeqCst);
 
             match incomplete.unavailable {
-                false => info!("I Got removed from 1 server"),
+                false => {
+                    info!("I Got removed from 1 server");
+                    // Its data is kept for a while in case the bot gets added back
+                    GuildSettingsRepo::new(&mut data.pool.acquire().await?).mark_left(incomplete.id.into()).await?;
+                    // The guild's settings may be purged before the bot comes back
+                    data.prefixes.forget(incomplete.id);
+                }
                 true => info!("A server I was serving became unavailable"),
             }
 
@@ -95,16 +183,106 @@ pub async fn event_handler(
 
         FullEvent::GuildMemberAddition { new_member } => {
             // New user joined the guild, check if they were a member before / are in the Database
-            let conn = data.pool.acquire().await?;
-            let role =
-                get_color(conn, new_member.user.id.into(), new_member.guild_id.into()).await?;
-
-            match role {
-                Some(role) => {
-                    // Found matching role
-                    new_member.add_role(&ctx.http, role.role_id).await?;
+            let role = ColorRepo::new(&mut data.pool.acquire().await?)
+                .get(new_member.user.id.into(), new_member.guild_id.into())
+                .await?;
+
+            if let Some(role) = role {
+                // Found matching role - the rules might have changed while they were gone
+                if let Some(reason) = check_eligibility(&data.pool, new_member).await? {
+                    info!("Not restoring color of user {} - {}", new_member.user.id, reason);
+                    return Ok(());
+                }
+
+                let role_id = Some(RoleId::from(role.role_id));
+                if let Some(problem) = preflight(&ctx.http, new_member.guild_id, role_id).await? {
+                    info!("Can't restore color of user {} - {}", new_member.user.id, problem);
+                    return Ok(());
+                }
+
+                if let Err(err) = restore_color_role(&ctx.http, &data.pool, new_member, &role).await {
+                    match describe_error(&err) {
+                        Some(message) => info!("Can't restore color of user {} - {}", new_member.user.id, message),
+                        None => return Err(err),
+                    }
+                }
+            }
+        }
+
+        FullEvent::GuildMemberRemoval { guild_id, user, member_data_if_available: _ } => {
+            // No point in spending the edit budget on someone who is gone
+            stop_member_cycle(&ctx.http, &data.pool, *guild_id, user.id).await?;
+
+            let mut conn = data.pool.acquire().await?;
+            let settings = GuildSettingsRepo::new(&mut conn).get_color_settings((*guild_id).into()).await?;
+
+            if settings.is_some_and(|s| s.remove_on_leave) {
+                let role = ColorRepo::new(&mut conn).get(user.id.into(), (*guild_id).into()).await?;
+                drop(conn);
+
+                if let Some(role) = role {
+                    // Only the role goes - the row is kept so the color can be restored when they come back
+                    match guild_id.delete_role(&ctx.http, RoleId::from(role.role_id)).await {
+                        Ok(_) => info!("Removed color role of user {} who left", user.id),
+                        Err(err) if is_not_found(&err) => {}
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+        }
+
+        FullEvent::GuildMemberUpdate { old_if_available: _, new: _, event } => {
+            // Color roles can be named after the member, keep them up to date with name changes
+            sync_role_name(&ctx.http, &data.pool, event.guild_id, &event.user, event.nick.as_deref()).await?;
+
+            // Cycling colors are a booster perk
+            if event.premium_since.is_none() && stop_member_cycle(&ctx.http, &data.pool, event.guild_id, event.user.id).await? {
+                info!("Stopped color cycle of user {} who is no longer boosting", event.user.id);
+            }
+        }
+
+        FullEvent::GuildRoleDelete { guild_id, removed_role_id, removed_role_data_if_available: _ } => {
+            let role = ColorRepo::new(&mut data.pool.acquire().await?).get_by_role((*removed_role_id).into()).await?;
+
+            if let Some(role) = role {
+                // Roles of members that left are kept in the database, they get recreated when the member returns
+                match guild_id.member(&ctx.http, UserId::from(role.uid)).await {
+                    Ok(_) => {
+                        ColorRepo::new(&mut data.pool.acquire().await?).delete(role.role_id).await?;
+                        info!("Color role of user {} got deleted, removed it from the database", role.uid);
+                    }
+                    Err(err) if is_not_found(&err) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+
+        FullEvent::GuildRoleUpdate { old_data_if_available: _, new } => {
+            // Keep the database in sync with changes made through the server settings
+            let role = ColorRepo::new(&mut data.pool.acquire().await?).get_by_role(new.id.into()).await?;
+
+            if let Some(role) = role {
+                // The bot marks the colors it is giving a role and stores them right after the edit went through,
+                // only an unmarked color while the stored one is still the one read here means someone else changed the role
+                let mut conn = data.pool.acquire().await?;
+                let mut repo = ColorRepo::new(&mut conn);
+                let changed = role.color != new.colour.0
+                    && !repo.is_pending_color(role.role_id, new.colour.0).await?
+                    && repo.swap_color(role.role_id, role.color, new.colour.0).await?;
+
+                if changed {
+                    repo.add_history(role.uid, role.guid, Some(role.color), new.colour.0, ColorSource::Admin).await?;
+                }
+                if role.role_name != new.name {
+                    repo.update_name(role.role_id, new.name.clone()).await?;
+                }
+                drop(conn);
+
+                if changed {
+                    // Discord does not tell who edited the role, the audit log of the server has that
+                    let change = ColorChange { uid: role.uid.into(), actor: None, victim: None, old_color: Some(role.color), color: Some(new.colour.0), source: ColorSource::Admin };
+                    log_color_change(&ctx.http, &data.pool, new.guild_id, change).await?;
                 }
-                None => {}
             }
         }
 
@@ -126,36 +304,32 @@ pub async fn event_handler(
                         .get_message(add_reaction.channel_id, add_reaction.message_id)
                         .await?;
 
-                    let conn = data.pool.acquire().await?;
-                    let exists = message_exists(conn, message.id.get()).await?;
+                    let exists = StarboardRepo::new(&mut data.pool.acquire().await?).message_exists(message.id.into()).await?;
 
                     if !exists {
                         // Try to find guild, starboard related settings
-                        let conn = data.pool.acquire().await?;
-                        let settings = get_guild_settings(conn, guild.get()).await?;
+                        let settings = GuildSettingsRepo::new(&mut data.pool.acquire().await?).get(guild.into()).await?;
 
                         if let Some(settings) = settings {
-                            if settings.starboard_enabled && settings.starboard_channel.is_some() {
+                            if let (true, Some(star_channel)) = (settings.starboard_enabled, settings.starboard_channel) {
                                 let star_count = message
                                     .reactions
                                     .iter()
-                                    .find(|f| {
+                                    .any(|f| {
                                         f.reaction_type == star
                                             && f.count == settings.starboard_min as u64
-                                    })
-                                    .is_some();
+                                    });
 
-                                let star_channel = settings.starboard_channel.unwrap();
+                                let starboard = ChannelId::from(star_channel);
 
-                                if star_channel != add_reaction.channel_id.get() && star_count {
-                                    let starboard = ChannelId::from(star_channel);
+                                if starboard != add_reaction.channel_id && star_count {
 
                                     let user = &message.author;
                                     let nick = message
                                         .author_nick(&ctx.http)
                                         .await
                                         .unwrap_or(user.name.clone());
-                                    let footer = CreateEmbedFooter::new(format!("CyberBun - ⭐"));
+                                    let footer = CreateEmbedFooter::new("CyberBun - ⭐");
 
                                     let msg = CreateEmbed::default()
                                         .title(nick)
@@ -166,8 +340,7 @@ pub async fn event_handler(
                                         .timestamp(Timestamp::now());
                                     let reply = CreateMessage::default().embed(msg);
 
-                                    let conn = data.pool.acquire().await?;
-                                    insert_message(conn, message.id.get()).await?;
+                                    StarboardRepo::new(&mut data.pool.acquire().await?).insert_message(guild.into(), message.id.into()).await?;
 
                                     starboard.send_message(&ctx.http, reply).await?;
                                 }
@@ -184,12 +357,11 @@ pub async fn event_handler(
     Ok(())
 }
 
-async fn reminder_handler(ctx: &Context, pool: &Arc<Pool<Sqlite>>) -> Result<(), Error> {
+async fn reminder_handler(ctx: &Context, pool: &Arc<DbPool>) -> Result<(), Error> {
     // First load all reminders from database that;
     // - have not been completed yet
     // - Have timestamps equal to 'now' or already in the 'past'
-    let conn = pool.acquire().await?;
-    let reminders: Vec<Reminder> = get_expired_reminders(conn).await?;
+    let reminders: Vec<Reminder> = ReminderRepo::new(&mut pool.acquire().await?).get_expired().await?;
 
     for r in reminders.iter() {
         let user = UserId::from(r.user_id);
@@ -199,8 +371,7 @@ async fn reminder_handler(ctx: &Context, pool: &Arc<Pool<Sqlite>>) -> Result<(),
         let chan = ChannelId::from(r.channel_id);
         chan.send_message(&ctx.http, message).await?;
 
-        let conn = pool.acquire().await?;
-        set_completed(conn, r.id).await?;
+        ReminderRepo::new(&mut pool.acquire().await?).set_completed(r.id).await?;
     }
     
     Ok(())
@@ -1,23 +1,36 @@
-use std::{env, sync::atomic::{AtomicBool, AtomicUsize}};
-use sqlx::{migrate::MigrateDatabase, sqlite::SqlitePoolOptions, SqlitePool};
-use tracing::info;
+use std::{env, path::Path, process, sync::atomic::{AtomicBool, AtomicUsize}};
+use tracing::{error, info};
 use tracing_subscriber::{fmt, EnvFilter, prelude::*};
 use poise::serenity_prelude::{ClientBuilder, GatewayIntents};
 
 // =================================================================
+mod color_math;
+mod color_roles;
 mod commands;
+mod config;
 mod db_handlers;
 mod event_handler;
+mod prefix;
 use event_handler::event_handler;
 
+use crate::config::Config;
+use crate::db_handlers::backend::DbPool;
 use crate::db_handlers::build_db;
+use crate::db_handlers::maintenance::restore_backup;
+use crate::prefix::PrefixCache;
 
 
 // == GLOBAL DATA ==
 struct Data { 
     server_count: AtomicUsize, 
-    pool: SqlitePool, 
-    reminder_task_running: AtomicBool
+    pool: DbPool, 
+    config: Config,
+    prefixes: PrefixCache,
+    reminder_task_running: AtomicBool,
+    color_job_task_running: AtomicBool,
+    color_cycle_task_running: AtomicBool,
+    backup_task_running: AtomicBool,
+    retention_task_running: AtomicBool,
 } // User data, which is stored and accessible in all command invocations
 type Error = Box<dyn std::error::Error + Send + Sync>;
 type Context<'a> = poise::Context<'a, Data, Error>;
@@ -26,38 +39,72 @@ type Context<'a> = poise::Context<'a, Data, Error>;
 #[tokio::main]
 async fn main() {
 
-    match dotenv::dotenv() {
-        Ok(env) => env,
-        Err(err) => panic!("Error launching environment: {}", err),
+    // A .env file is optional, the settings can just as well come from the config file
+    dotenv::dotenv().ok();
+
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("{}", errors);
+            process::exit(1);
+        }
     };
 
-    logger_setup().await;
+    logger_setup(&config.filter).await;
+
+    // `cyber_bun2 restore <backup file>` puts a backup in place of the database before the bot starts
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("restore") {
+        let Some(backup) = args.get(2) else {
+            eprintln!("Usage: cyber_bun2 restore <backup file>");
+            process::exit(2);
+        };
+
+        if let Err(err) = restore_backup(Path::new(backup), &config.database_url).await {
+            error!("Error restoring backup: {}", err);
+            process::exit(1);
+        }
+    }
 
-    let pool = match db_setup().await {
+    let pool = match db_setup(&config).await {
         Ok(pool) => pool,
-        Err(err) => panic!("Error setting up Database pool: {}", err),
+        Err(err) => {
+            error!("Error setting up Database pool: {}", err);
+            process::exit(1);
+        }
     };
 
-    let bot_token = env::var("CYBERBUN_TOKEN").expect("ERROR: CYBERBUN_TOKEN NOT FOUND");   
-    let intents = GatewayIntents::non_privileged() | GatewayIntents::MESSAGE_CONTENT;
+    let bot_token = config.token.clone();
+    let intents = GatewayIntents::non_privileged()
+        | GatewayIntents::MESSAGE_CONTENT
+        | GatewayIntents::GUILD_MEMBERS;
 
     let commands = vec![
         commands::help(),
         commands::register_commands(),
         commands::sysinfo(),
 
+        commands::database::db(),
+
         commands::colors::color(),
 
         commands::starboard::starboard(),
 
         commands::reminders::remindme(),
+
+        commands::privacy::privacy(),
+
+        commands::settings::settings(),
     ];
 
     let options = poise::FrameworkOptions {
         commands,
 
+        // Guilds can pick their own prefix, a guild's prefix replaces the configured one
         prefix_options: poise::PrefixFrameworkOptions {
-            prefix: Some("~".into()),
+            prefix: None,
+            dynamic_prefix: Some(|ctx| Box::pin(prefix::dynamic_prefix(ctx))),
+            mention_as_prefix: true,
             ..Default::default()
         },
 
@@ -75,61 +122,57 @@ async fn main() {
                 Ok(Data {
                     server_count: AtomicUsize::new(0),
                     pool,
+                    config,
+                    prefixes: PrefixCache::default(),
                     reminder_task_running: AtomicBool::new(false),
+                    color_job_task_running: AtomicBool::new(false),
+                    color_cycle_task_running: AtomicBool::new(false),
+                    backup_task_running: AtomicBool::new(false),
+                    retention_task_running: AtomicBool::new(false),
                 })
             })
         })
         .build();
 
-    let client = ClientBuilder::new(bot_token, intents)
-        .framework(framework)
-        .await;
+    let mut client = match ClientBuilder::new(bot_token, intents).framework(framework).await {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Error creating the client: {}", err);
+            process::exit(1);
+        }
+    };
 
     info!("Starting CyberBunny...");
 
-    client.unwrap().start().await.unwrap();
+    // A token Discord doesn't accept only shows up once the gateway connects
+    if let Err(err) = client.start().await {
+        error!("Error running the client: {}", err);
+        process::exit(1);
+    }
 }
 
 
-async fn logger_setup() {
-    let filter = EnvFilter::from_env("CYBERBUN_FILTER");
+async fn logger_setup(filter: &str) {
+    // The filter was checked when the config got loaded
+    let env_filter = EnvFilter::new(filter);
 
     tracing_subscriber::registry()
         .with(fmt::layer())
-        .with(filter)
+        .with(env_filter)
         .init();
 
-    info!("Logger ready - Filter {:?}", env::var("CYBERBUN_FILTER").unwrap());
+    info!("Logger ready - Filter {:?}", filter);
 }
 
-async fn db_setup() -> Result<SqlitePool, Error> {
-
-    let db_url = env::var("CYBERBUN_DB_URL").expect("NO DATABASE URL FOUND");
-
-    if !sqlx::Sqlite::database_exists(&db_url).await? {
-        sqlx::Sqlite::create_database(&db_url).await?;
-    }
+async fn db_setup(config: &Config) -> Result<DbPool, Error> {
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(10)
-        .connect(&db_url)
-        .await?;
+    let pool = DbPool::connect(&config.database_url, &config.database).await?;
 
-    info!("DB Pool ready ~ making sure tables exist");
+    info!("DB Pool ready ({:?}) ~ making sure tables exist", pool.backend());
 
-    let conn = pool.acquire().await?;
-    build_db::build_colors(conn).await?;
-    
-    let conn = pool.acquire().await?;
-    build_db::build_guild_settings(conn).await?;
-
-    let conn = pool.acquire().await?;
-    build_db::build_starred_messages(conn).await?;
-
-    let conn = pool.acquire().await?;
-    build_db::build_reminders(conn).await?;
+    build_db::build_tables(&mut pool.acquire().await?).await?;
 
     info!("Database pool ready");
 
     Ok(pool)
-}
\ No newline at end of file
+}
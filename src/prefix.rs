@@ -0,0 +1,107 @@
+//! Text command prefixes - every guild can pick its own, everywhere else the configured prefix is used.
+//! The prefix is needed for every message the bot sees, so the guild prefixes are cached and only read from the database once.
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use poise::serenity_prelude::GuildId;
+
+use crate::db_handlers::guild_settings_repo::GuildSettingsRepo;
+use crate::{Data, Error};
+
+pub const MAX_PREFIX_LENGTH: usize = 16;
+
+/// Prefixes of the guilds that sent a message since the bot started, None for the ones using the configured prefix
+#[derive(Default)]
+pub struct PrefixCache {
+    guilds: RwLock<HashMap<GuildId, Option<String>>>,
+}
+
+impl PrefixCache {
+    fn get(&self, guild: GuildId) -> Option<Option<String>> {
+        self.guilds.read().expect("Prefix cache poisoned").get(&guild).cloned()
+    }
+
+    /// Has to be called with every change to a guild's prefix
+    pub fn set(&self, guild: GuildId, prefix: Option<String>) {
+        self.guilds.write().expect("Prefix cache poisoned").insert(guild, prefix);
+    }
+
+    pub fn forget(&self, guild: GuildId) {
+        self.guilds.write().expect("Prefix cache poisoned").remove(&guild);
+    }
+}
+
+/// Prefixes can't be empty, contain spaces or mentions or be longer than `MAX_PREFIX_LENGTH`
+pub fn is_valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty()
+        && prefix.chars().count() <= MAX_PREFIX_LENGTH
+        && !prefix.contains(char::is_whitespace)
+        && !is_mention(prefix)
+}
+
+/// User, role and channel mentions, `@everyone` and `@here` - Discord turns them into pings or links, so they can't be typed as a prefix
+fn is_mention(prefix: &str) -> bool {
+    ["<@", "<#", "@everyone", "@here"].iter().any(|mention| prefix.contains(mention))
+}
+
+/// The prefix for a message, the one of its guild or the configured one
+pub async fn dynamic_prefix(ctx: poise::PartialContext<'_, Data, Error>) -> Result<Option<String>, Error> {
+    let Some(guild) = ctx.guild_id else {
+        return Ok(Some(ctx.data.config.prefix.clone()));
+    };
+
+    let prefix = match ctx.data.prefixes.get(guild) {
+        Some(prefix) => prefix,
+        None => {
+            let prefix = GuildSettingsRepo::new(&mut ctx.data.pool.acquire().await?).get_prefix(guild.into()).await?;
+            ctx.data.prefixes.set(guild, prefix.clone());
+            prefix
+        }
+    };
+
+    Ok(Some(prefix.unwrap_or_else(|| ctx.data.config.prefix.clone())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_prefixes_are_valid() {
+        assert!(is_valid_prefix("~"));
+        assert!(is_valid_prefix("!"));
+        assert!(is_valid_prefix("bun!"));
+        assert!(is_valid_prefix("@"));
+    }
+
+    #[test]
+    fn empty_prefix_is_invalid() {
+        assert!(!is_valid_prefix(""));
+    }
+
+    #[test]
+    fn length_is_counted_in_characters() {
+        assert!(is_valid_prefix(&"!".repeat(MAX_PREFIX_LENGTH)));
+        assert!(!is_valid_prefix(&"!".repeat(MAX_PREFIX_LENGTH + 1)));
+        assert!(is_valid_prefix(&"é".repeat(MAX_PREFIX_LENGTH)));
+    }
+
+    #[test]
+    fn whitespace_is_invalid() {
+        assert!(!is_valid_prefix("a b"));
+        assert!(!is_valid_prefix(" !"));
+        assert!(!is_valid_prefix("!\t"));
+        assert!(!is_valid_prefix("!\n"));
+    }
+
+    #[test]
+    fn mentions_are_invalid() {
+        assert!(!is_valid_prefix("<@123>"));
+        assert!(!is_valid_prefix("<@!123>"));
+        assert!(!is_valid_prefix("<@&123>"));
+        assert!(!is_valid_prefix("<#123>"));
+        assert!(!is_valid_prefix("@everyone"));
+        assert!(!is_valid_prefix("@here!"));
+    }
+}